@@ -0,0 +1,309 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use toml::Value;
+
+// Lines printed by orca, or by mpirun, when a run fails. SCF NOT CONVERGED is
+// left out, orca also prints it for SCFs that recover later in the run.
+pub const ERROR_BANNERS: [&str; 7] = [
+    "ORCA finished by error termination",
+    "ABORTING THE RUN",
+    "INPUT ERROR",
+    "UNRECOGNIZED OR DUPLICATED KEYWORD",
+    "TERMINATED ABNORMALLY",
+    "mpirun noticed that process",
+    "MPI_ABORT was invoked",
+];
+
+// Results read from an orca output. The fields are None when the output does
+// not contain them, e.g. optcycles for a single point.
+#[derive(Debug, Default)]
+pub struct Summary {
+    // The output ends with the normal termination banner
+    pub terminated: bool,
+    // Last FINAL SINGLE POINT ENERGY, in Eh
+    pub energy: Option<f64>,
+    pub optcycles: Option<u64>,
+    pub converged: Option<bool>,
+    // Imaginary modes of the last frequency calculation
+    pub imaginary: Option<u64>,
+    // TOTAL RUN TIME of the timing footer, in seconds
+    pub runtime: Option<u64>,
+    // Last error banner found
+    pub error: Option<String>,
+}
+
+impl Summary {
+    // The [summary] table of the job file. The error is only kept for runs
+    // that did not terminate normally.
+    pub fn to_toml(&self) -> Value {
+        let mut table = toml::Table::new();
+        if let Some(energy) = self.energy {table.insert("energy".to_string(), Value::Float(energy));}
+        if let Some(optcycles) = self.optcycles {table.insert("optcycles".to_string(), Value::Integer(optcycles as i64));}
+        if let Some(converged) = self.converged {table.insert("converged".to_string(), Value::Boolean(converged));}
+        if let Some(imaginary) = self.imaginary {table.insert("imaginary".to_string(), Value::Integer(imaginary as i64));}
+        if let Some(runtime) = self.runtime {table.insert("runtime".to_string(), Value::Integer(runtime as i64));}
+        if let Some(error) = self.error.as_ref().filter(|_| !self.terminated) {
+            table.insert("error".to_string(), Value::String(error.clone()));
+        }
+        Value::Table(table)
+    }
+}
+
+// Parses "TOTAL RUN TIME: 0 days 1 hours 2 minutes 3 seconds 456 msec" into seconds
+fn parse_runtime(line: &str) -> Option<u64> {
+    let tokens = line.split(':').nth(1)?.split_whitespace().collect::<Vec<&str>>();
+    let mut seconds = 0;
+    for pair in tokens.chunks(2) {
+        let [number, unit] = pair else {return None};
+        let number = number.parse::<u64>().ok()?;
+        seconds += match *unit {
+            "days" | "day" => number * 24 * 60 * 60,
+            "hours" | "hour" => number * 60 * 60,
+            "minutes" | "minute" => number * 60,
+            "seconds" | "second" => number,
+            "msec" => 0,
+            _ => return None,
+        };
+    }
+    Some(seconds)
+}
+
+// Reads the whole output line by line, so that the energies and cycles of a
+// long optimization are found too
+pub fn analyze(outpath: &Path) -> io::Result<Summary> {
+    let mut reader = BufReader::new(File::open(outpath)?);
+    let mut summary = Summary::default();
+    let mut buffer = vec![];
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {break}
+        // Outputs may contain invalid utf-8, e.g. in file names
+        let line = String::from_utf8_lossy(&buffer);
+        let line = line.trim();
+
+        if line.contains("****ORCA TERMINATED NORMALLY****") {
+            summary.terminated = true;
+        } else if line.starts_with("FINAL SINGLE POINT ENERGY") {
+            summary.energy = line.split_whitespace().last().and_then(|e| e.parse().ok()).or(summary.energy);
+        } else if line.contains("GEOMETRY OPTIMIZATION CYCLE") {
+            let cycle = line.split_whitespace().find_map(|t| t.parse::<u64>().ok());
+            summary.optcycles = cycle.max(summary.optcycles);
+        } else if line.contains("THE OPTIMIZATION HAS CONVERGED") {
+            summary.converged = Some(true);
+        } else if line.contains("VIBRATIONAL FREQUENCIES") {
+            summary.imaginary = Some(0);
+        } else if line.contains("***imaginary mode***") {
+            summary.imaginary = Some(summary.imaginary.unwrap_or(0) + 1);
+        } else if line.starts_with("TOTAL RUN TIME") {
+            summary.runtime = parse_runtime(line);
+        } else if ERROR_BANNERS.iter().any(|b| line.contains(b)) {
+            summary.error = Some(line.to_string());
+        }
+    }
+    if summary.optcycles.is_some() && summary.converged.is_none() {summary.converged = Some(false);}
+    Ok(summary)
+}
+
+// Bytes read from the end of a growing output to tell its progress
+const PROGRESS_TAIL: u64 = 256 * 1024;
+
+// Current phase of a running orca job, read from the end of its output
+#[derive(Debug, Default, PartialEq)]
+pub struct Progress {
+    pub optcycle: Option<u64>,
+    // Last energy and gradient norms of the optimization, in Eh and Eh/bohr
+    pub energy: Option<f64>,
+    pub rmsgradient: Option<f64>,
+    pub maxgradient: Option<f64>,
+    // Iteration of an SCF that did not converge yet
+    pub scfiteration: Option<u64>,
+    // Displaced geometry of a numerical frequency calculation, and their number
+    pub displacement: Option<(u64, u64)>,
+}
+
+impl Progress {
+    // e.g. "geometry cycle 3, E = -1.165432 Eh, RMS gradient 0.000123, SCF iteration 7"
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if let Some((displacement, total)) = self.displacement {parts.push(format!("frequency displacement {}/{}", displacement, total));}
+        if let Some(cycle) = self.optcycle {parts.push(format!("geometry cycle {}", cycle));}
+        if let Some(energy) = self.energy {parts.push(format!("E = {:.6} Eh", energy));}
+        if let Some(gradient) = self.rmsgradient {parts.push(format!("RMS gradient {:.6}", gradient));}
+        if let Some(gradient) = self.maxgradient {parts.push(format!("max gradient {:.6}", gradient));}
+        if let Some(iteration) = self.scfiteration {parts.push(format!("SCF iteration {}", iteration));}
+        parts.join(", ")
+    }
+}
+
+// Reads the complete lines of the last bytes of a file. The first line is
+// dropped when the window starts in the middle of it, so that lines of any
+// length are either whole or absent.
+pub fn read_tail(path: &Path, bytes: u64) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let size = file.seek(SeekFrom::End(0))?;
+    let start = size.saturating_sub(bytes);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer)?;
+    let content = String::from_utf8_lossy(&buffer);
+    let mut lines = content.lines().map(|l| l.to_string()).collect::<Vec<String>>();
+    if start > 0 && !lines.is_empty() {lines.remove(0);}
+    Ok(lines)
+}
+
+// Parses "<< Calculating on displaced geometry 5 (of 36) >>"
+fn parse_displacement(line: &str) -> Option<(u64, u64)> {
+    let rest = line.split("displaced geometry").nth(1)?;
+    let numbers = rest.split(|c: char| !c.is_ascii_digit()).filter(|n| !n.is_empty()).collect::<Vec<&str>>();
+    match numbers.as_slice() {
+        [displacement, total, ..] => Some((displacement.parse().ok()?, total.parse().ok()?)),
+        _ => None,
+    }
+}
+
+// Reads the progress of a running job from the end of its output
+pub fn progress(outpath: &Path) -> io::Result<Progress> {
+    let mut progress = Progress::default();
+    let mut scf = false;
+    for line in read_tail(outpath, PROGRESS_TAIL)? {
+        let line = line.trim();
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        if line.contains("GEOMETRY OPTIMIZATION CYCLE") {
+            progress.optcycle = tokens.iter().find_map(|t| t.parse().ok());
+            progress.scfiteration = None;
+        } else if line.contains("displaced geometry") {
+            progress.displacement = parse_displacement(line).or(progress.displacement);
+        } else if line.starts_with("FINAL SINGLE POINT ENERGY") {
+            progress.energy = tokens.last().and_then(|e| e.parse().ok()).or(progress.energy);
+        } else if line.starts_with("RMS gradient") {
+            progress.rmsgradient = tokens.get(2).and_then(|g| g.parse().ok());
+        } else if line.starts_with("MAX gradient") {
+            progress.maxgradient = tokens.get(2).and_then(|g| g.parse().ok());
+        } else if line.contains("SCF ITERATIONS") {
+            scf = true;
+            progress.scfiteration = None;
+        } else if line.contains("SCF CONVERGED") || line.contains("SCF NOT CONVERGED") {
+            scf = false;
+            progress.scfiteration = None;
+        } else if scf {
+            // Iteration lines start with the iteration and the energy
+            if let [iteration, energy, ..] = tokens.as_slice() {
+                if let (Ok(iteration), Ok(_)) = (iteration.parse::<u64>(), energy.parse::<f64>()) {
+                    progress.scfiteration = Some(iteration);
+                }
+            }
+        }
+    }
+    Ok(progress)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Writes the content to a file of the temporary directory, unique per test
+    fn output(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("orcajob-{}-{}.out", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn summary(name: &str, content: &str) -> Summary {
+        let path = output(name, content);
+        let summary = analyze(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        summary
+    }
+
+    #[test]
+    fn runtime_footer() {
+        assert_eq!(parse_runtime("TOTAL RUN TIME: 0 days 1 hours 2 minutes 3 seconds 456 msec"), Some(3723));
+        assert_eq!(parse_runtime("TOTAL RUN TIME: 2 days 0 hours 0 minutes 0 seconds 0 msec"), Some(172800));
+        let summary = summary("runtime", "                             ****ORCA TERMINATED NORMALLY****\nTOTAL RUN TIME: 0 days 0 hours 0 minutes 12 seconds 345 msec\n");
+        assert!(summary.terminated);
+        assert_eq!(summary.runtime, Some(12));
+    }
+
+    #[test]
+    fn malformed_runtime() {
+        assert_eq!(parse_runtime("TOTAL RUN TIME"), None);
+        assert_eq!(parse_runtime("TOTAL RUN TIME: 0 days 1 hours 2"), None);
+        assert_eq!(parse_runtime("TOTAL RUN TIME: zero days"), None);
+        assert_eq!(parse_runtime("TOTAL RUN TIME: 3 fortnights"), None);
+    }
+
+    #[test]
+    fn imaginary_modes_of_last_frequencies() {
+        let block = |modes: &[&str]| {
+            let lines = modes.iter().enumerate()
+                .map(|(i, m)| format!("   {}:   {} cm**-1{}\n", i + 6, m, if m.starts_with('-') {" ***imaginary mode***"} else {""}))
+                .collect::<String>();
+            format!("-----------------------\nVIBRATIONAL FREQUENCIES\n-----------------------\n\n{}\n", lines)
+        };
+        let content = format!("{}{}", block(&["-412.33", "-35.10", "120.52"]), block(&["-20.04", "118.77", "402.91"]));
+        assert_eq!(summary("imaginary", &content).imaginary, Some(1));
+        assert_eq!(summary("realmodes", &block(&["118.77", "402.91"])).imaginary, Some(0));
+    }
+
+    #[test]
+    fn optimization_without_convergence() {
+        let cycle = |n: u64| format!("                         *        GEOMETRY OPTIMIZATION CYCLE   {}            *\nFINAL SINGLE POINT ENERGY      -1.16{}000000\n", n, n);
+        let unconverged = summary("unconverged", &(cycle(1) + &cycle(2)));
+        assert_eq!((unconverged.optcycles, unconverged.converged), (Some(2), Some(false)));
+        assert_eq!(unconverged.energy, Some(-1.162));
+
+        let converged = summary("converged", &(cycle(1) + "                    ***        THE OPTIMIZATION HAS CONVERGED     ***\n"));
+        assert_eq!((converged.optcycles, converged.converged), (Some(1), Some(true)));
+        assert_eq!(summary("singlepoint", "FINAL SINGLE POINT ENERGY      -1.1\n").converged, None);
+    }
+
+    #[test]
+    fn error_dropped_when_terminated() {
+        let failed = summary("failed", "INPUT ERROR\nORCA finished by error termination in SCF\n");
+        assert!(!failed.terminated);
+        assert_eq!(failed.error.as_deref(), Some("ORCA finished by error termination in SCF"));
+        assert!(failed.to_toml().get("error").is_some());
+
+        let recovered = summary("recovered", "ABORTING THE RUN\n****ORCA TERMINATED NORMALLY****\n");
+        assert!(recovered.terminated);
+        assert!(recovered.to_toml().get("error").is_none());
+    }
+
+    #[test]
+    fn scf_not_converged_is_no_error() {
+        let summary = summary("scf", "SCF NOT CONVERGED AFTER 125 CYCLES\n****ORCA TERMINATED NORMALLY****\n");
+        assert!(summary.terminated);
+        assert_eq!(summary.error, None);
+    }
+
+    #[test]
+    fn tail_starting_mid_line() {
+        let path = output("tail", "first line\nsecond line\nthird line\n");
+        assert_eq!(read_tail(&path, 26).unwrap(), ["second line", "third line"]);
+        assert_eq!(read_tail(&path, 1024).unwrap(), ["first line", "second line", "third line"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn displacement() {
+        assert_eq!(parse_displacement("<< Calculating on displaced geometry 5 (of 36) >>"), Some((5, 36)));
+        assert_eq!(parse_displacement("<< Calculating on displaced geometry >>"), None);
+    }
+
+    #[test]
+    fn scf_iterations() {
+        let scf = "               SCF ITERATIONS\nITER       Energy         Delta-E        Max-DP      RMS-DP      [F,P]     Damp\n  0     -1.1166666   0.000000000000 0.01 0.005 0.02 0.700\n  1     -1.1234567  -0.006790063512 0.01 0.004 0.01 0.700\n";
+        let path = output("scf", scf);
+        assert_eq!(progress(&path).unwrap().scfiteration, Some(1));
+
+        let content = format!("<< Calculating on displaced geometry 5 (of 36) >>\n{}                     *****************************************************\n                     *                     SUCCESS                       *\n                     *   SCF CONVERGED AFTER   8 CYCLES                  *\n", scf);
+        fs::write(&path, content).unwrap();
+        let progress = progress(&path).unwrap();
+        assert_eq!(progress.scfiteration, None);
+        assert_eq!(progress.displacement, Some((5, 36)));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use crate::common::{jobs_fold, read_jobfile, timestamp, write_jobfile};
+use crate::copyback::copy_results;
+use crate::store::{needs_migration, read_jobs, State, Store};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::time::UNIX_EPOCH;
+
+pub struct Collected {
+    pub id: String,
+    pub user: String,
+    // Seconds since the job ended
+    pub age: u64,
+}
+
+// Deletes the folders of the done jobs that ended more than deleteafter
+// seconds ago, and marks them as deleted in the job store. Results that were
+// not copied back yet are copied first, and jobs with after.pin = true are kept.
+// If user is set only the jobs of that user are collected. With dryrun
+// nothing is modified, the jobs that would be deleted are returned.
+pub fn collect_garbage(deleteafter: u64, user: Option<&str>, dryrun: bool) -> io::Result<Vec<Collected>> {
+    let now = timestamp();
+    let mut collected = vec![];
+    for record in read_jobs()? {
+        let age = now.saturating_sub(record.ended);
+        if record.state != State::Done || age < deleteafter {continue}
+        if user.is_some_and(|u| u != record.user) {continue}
+
+        let jobdir = jobs_fold().join(&record.id);
+        // A record whose folder was removed by hand is collected right away
+        if jobdir.exists() {
+            let (jobpath, mut jobtoml) = match read_jobfile(&jobdir) {
+                Err(_) => continue,
+                Ok(job) => job,
+            };
+            if jobtoml.get("after").and_then(|a| a.get("pin")).and_then(|v| v.as_bool()).unwrap_or(false) {continue}
+            let result = jobtoml.get("result");
+            let launched = result.and_then(|r| r.get("launched")).is_some();
+            let copied = result.and_then(|r| r.get("copied")).is_some();
+            if !dryrun && launched && !copied {
+                if let Err(e) = copy_results(&record.id, &mut jobtoml).and_then(|_| write_jobfile(&jobpath, &jobtoml)) {
+                    eprintln!("Cannot copy the results of job {}, keeping it: {}", record.id, e);
+                    continue;
+                }
+            }
+        }
+
+        if !dryrun {
+            // The record goes first, a crash before the folder is removed
+            // leaves an orphan folder that the next collection deletes
+            let store = Store::open()?;
+            store.update(&record.id, &[State::Done], |r| r.state = State::Deleted)?;
+            if jobdir.exists() {fs::remove_dir_all(&jobdir)?;}
+        }
+        collected.push(Collected { id: record.id, user: record.user, age });
+    }
+
+    // The jobs of invalid journal lines are missing from the store, so their
+    // folders would be taken for orphans, and compacting would drop the lines
+    if !dryrun {
+        let store = Store::open()?;
+        match store.invalid_lines()? {
+            0 => {
+                remove_orphans(&store)?;
+                store.compact()?;
+            },
+            invalid => eprintln!("The journal has {} invalid lines, orphan folders are kept and the journal is not compacted", invalid),
+        }
+    }
+    Ok(collected)
+}
+
+// Seconds after which a folder being copied by orcajob job is considered
+// abandoned
+const STAGING_AGE: u64 = 24 * 60 * 60;
+
+// Deletes the job folders without a job in the store, left behind by a crash
+// during a submission or a collection. Submissions copy the job to a hidden
+// .<id> folder first, which is only deleted once it is old enough. Nothing is
+// deleted while the job lists of older versions are not migrated, as their
+// jobs are not in the store yet.
+fn remove_orphans(store: &Store) -> io::Result<()> {
+    if needs_migration() {return Ok(())}
+    let ids = store.jobs()?.into_iter().map(|j| j.id).collect::<HashSet<String>>();
+    let now = timestamp();
+    for entry in fs::read_dir(jobs_fold())?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !entry.path().is_dir() || ids.contains(&name) {continue}
+        if name.starts_with('.') {
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()
+                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            if now.saturating_sub(modified) < STAGING_AGE {continue}
+        }
+        println!("Deleting orphan job folder {}", name);
+        fs::remove_dir_all(entry.path())?;
+    }
+    Ok(())
+}
@@ -1,50 +1,260 @@
-pub const JOBS_FILE: &str = "./env/jobs.txt";
-pub const JOBS_LOCK: &str = "./env/jobs.txt.lock";
+use fs2::FileExt;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs::File, path::Path, path::PathBuf};
+use toml::Value;
 
-pub const DONE_FILE: &str = "./env/done.txt";
-pub const DONE_LOCK: &str = "./env/done.txt.lock";
+// Root used when neither --root nor ORCAJOB_HOME are set, if it exists.
+// Otherwise each user gets their own root in $XDG_DATA_HOME/orcajob.
+pub const SYSTEM_ROOT: &str = "/var/lib/orcajob";
 
-pub const WORK_FILE: &str = "./env/work.txt";
-pub const WORK_LOCK: &str = "./env/work.txt.lock";
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
 
-pub const CONF_FILE: &str = "./env/orcarc";
+// Resolves the root of the environment from the --root flag, the ORCAJOB_HOME
+// variable or the fallback locations. Relative roots are made absolute, so that
+// the paths stay valid in the job folders.
+fn resolve_root(flag: Option<&str>) -> PathBuf {
+    let nonempty = |v: &std::ffi::OsString| !v.is_empty();
+    let root = match (flag, env::var_os("ORCAJOB_HOME").filter(nonempty)) {
+        (Some(flag), _) => PathBuf::from(flag),
+        (None, Some(home)) => PathBuf::from(home),
+        (None, None) if Path::new(SYSTEM_ROOT).is_dir() => PathBuf::from(SYSTEM_ROOT),
+        (None, None) => env::var_os("XDG_DATA_HOME").filter(nonempty).map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("orcajob"),
+    };
+    std::path::absolute(&root).unwrap_or(root)
+}
 
-pub const JOBS_FOLD: &str = "./env/jobs";
+// Sets the root from the --root flag. Must be called before any path is
+// used, later calls are ignored.
+pub fn set_root(flag: Option<&str>) {
+    let _ = ROOT.set(resolve_root(flag));
+}
 
-use fs2::FileExt;
-use std::io;
-use std::{fs::File, path::Path};
-use toml::Value;
+pub fn root() -> &'static Path {
+    ROOT.get_or_init(|| resolve_root(None))
+}
+
+// Job store, see store.rs
+pub fn journal_file() -> PathBuf {root().join("journal.log")}
+pub fn journal_lock() -> PathBuf {root().join("journal.log.lock")}
+
+// Plain-text job lists used before the job store, only read by orcajob migrate
+pub fn jobs_file() -> PathBuf {root().join("jobs.txt")}
+pub fn jobs_lock() -> PathBuf {root().join("jobs.txt.lock")}
+
+pub fn done_file() -> PathBuf {root().join("done.txt")}
+pub fn done_lock() -> PathBuf {root().join("done.txt.lock")}
+
+pub fn work_file() -> PathBuf {root().join("work.txt")}
+pub fn work_lock() -> PathBuf {root().join("work.txt.lock")}
+
+// Stop requests for running jobs, one "<id> <user>" per line, read by the daemon
+pub fn stop_file() -> PathBuf {root().join("stop.txt")}
+pub fn stop_lock() -> PathBuf {root().join("stop.txt.lock")}
+
+pub fn conf_file() -> PathBuf {root().join("orcarc")}
 
-pub fn acquire_lock(plock: &str) -> io::Result<File> {
-    let lock_path = Path::new(plock);
-    let lock = File::create(lock_path)?;
+// Unix socket of the daemon, see ipc.rs
+pub fn socket_file() -> PathBuf {root().join("orcajobd.sock")}
+
+pub fn jobs_fold() -> PathBuf {root().join("jobs")}
+
+pub fn acquire_lock(plock: &Path) -> io::Result<File> {
+    let lock = File::create(plock)?;
     lock.try_lock_exclusive()?;
 
-    return Ok(lock);
+    Ok(lock)
 }
-pub fn acquire_lock_wait(plock: &str) -> io::Result<File> {
-    let lock_path = Path::new(plock);
-    let lock = File::create(lock_path)?;
+pub fn acquire_lock_wait(plock: &Path) -> io::Result<File> {
+    let lock = File::create(plock)?;
     lock.lock_exclusive()?;
 
-    return Ok(lock);
+    Ok(lock)
 }
 pub fn release_lock(lock: &File) -> io::Result<()> {
     lock.unlock()?;
     Ok(())
 }
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error").as_secs()
+}
+
+pub fn findfile(path: &Path, ext: &str) -> Option<PathBuf> {
+    match fs::read_dir(path) {
+        Err(_) => None,
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let fname = entry.file_name();
+                if let Some(fname) = fname.to_str() {
+                    if fname.ends_with(ext) {
+                        return Some(path.join(fname));
+                    }
+                }
+            } None
+        }
+    }
+}
+
+// Reads the compiled .job file of a job folder, returning its path and content
+pub fn read_jobfile(jobdir: &Path) -> io::Result<(PathBuf, Value)> {
+    let jobpath = match findfile(jobdir, ".job") {
+        None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("Jobfile not found in {}", jobdir.display()))),
+        Some(jobpath) => jobpath,
+    };
+    let content = fs::read_to_string(&jobpath)?;
+    match content.parse::<Value>() {
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Error in parsing TOML jobfile {}", jobpath.display()))),
+        Ok(jobtoml) => Ok((jobpath, jobtoml)),
+    }
+}
+
+// Writes the file to a temporary sibling and renames it in place, so that a
+// crash never leaves a truncated file behind
+pub fn write_atomic(path: &Path, content: &str) -> io::Result<()> {
+    let mut tmpname = path.as_os_str().to_owned();
+    tmpname.push(".tmp");
+    let tmppath = PathBuf::from(tmpname);
+    {
+        let mut tmpfile = File::create(&tmppath)?;
+        tmpfile.write_all(content.as_bytes())?;
+        tmpfile.sync_all()?;
+    }
+    fs::rename(tmppath, path)
+}
+
+pub fn write_jobfile(jobpath: &Path, jobtoml: &Value) -> io::Result<()> {
+    match toml::to_string_pretty(jobtoml) {
+        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        Ok(content) => write_atomic(jobpath, &content),
+    }
+}
+
+// Sets key = value in the given table of the job toml, creating the table if needed
+pub fn set_jobvalue(jobtoml: &mut Value, table: &str, key: &str, value: Value) {
+    if let Some(jobtable) = jobtoml.as_table_mut() {
+        let entry = jobtable.entry(table.to_string()).or_insert_with(|| Value::Table(toml::Table::new()));
+        if let Some(entry) = entry.as_table_mut() {
+            entry.insert(key.to_string(), value);
+        }
+    }
+}
+
+// Lists of one id per line, such as the stop requests and the job lists used
+// before the job store. These functions do not lock, the caller must hold the
+// corresponding lock.
+pub fn read_ids(path: &Path) -> io::Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e),
+        Ok(content) => Ok(content.lines()
+                            .map(|l| l.trim().to_string())
+                            .filter(|l| !l.is_empty())
+                            .collect()),
+    }
+}
+pub fn write_ids(path: &Path, ids: &[String]) -> io::Result<()> {
+    let mut content = ids.join("\n");
+    if !content.is_empty() {content.push('\n');}
+    write_atomic(path, &content)
+}
+pub fn append_id(path: &Path, id: &str) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", id)
+}
+
+// Parses a duration such as "90", "30m", "1h" or "2d12h" into seconds. A
+// number without a unit is in seconds.
+pub fn parse_duration(duration: &str) -> Option<u64> {
+    if duration.trim().is_empty() {return None}
+    let mut total: u64 = 0;
+    let mut number = String::new();
+    for c in duration.trim().chars() {
+        if c.is_ascii_digit() {number.push(c); continue;}
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(number.parse::<u64>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+    if !number.is_empty() {total = total.checked_add(number.parse::<u64>().ok()?)?;}
+    Some(total)
+}
+
+// Reads orcarc, falling back to the default values for missing keys
+pub fn read_orcarc(path: &Path) -> Value {
+    let default = ORCARC_DEFAULT.parse::<Value>().unwrap();
+    let mut conf = match fs::read_to_string(path).map(|c| c.parse::<Value>()) {
+        Ok(Ok(conf)) => conf,
+        // This line should never result in panic, the fixed values for orcarc
+        // should always work
+        _ => default.clone(),
+    };
+    merge_toml(&mut conf, &default);
+    conf
+}
+
+pub fn job_nprocs(job: &str) -> io::Result<usize> {
+    let (_, jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    Ok(jobtoml.get("scheduling")
+            .and_then(|s| s.get("nprocs"))
+            .and_then(|v| v.as_integer())
+            .unwrap_or(1) as usize)
+}
+
+pub fn is_admin(user: &str) -> bool {
+    let admins = read_orcarc(&conf_file()).get("admins").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    admins.iter().any(|a| a.as_str() == Some(user))
+}
+
+// Users can only act on their own jobs, unless they are listed in the admins
+// of orcarc
+pub fn check_owner(jobtoml: &Value, currentuser: &str) -> io::Result<()> {
+    let owner = jobtoml.get("launch").and_then(|l| l.get("username")).and_then(|v| v.as_str()).unwrap_or("notset");
+    if owner == currentuser || is_admin(currentuser) {return Ok(())}
+    Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("Job belongs to {}", owner)))
+}
+
+// Name of the user with the given uid, read from the password database
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe {std::mem::zeroed()};
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let code = unsafe {libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)};
+    if code != 0 || result.is_null() {return None}
+    let name = unsafe {std::ffi::CStr::from_ptr(passwd.pw_name)};
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Uid of the user with the given name, read from the password database
+pub fn user_id(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let mut passwd: libc::passwd = unsafe {std::mem::zeroed()};
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = std::ptr::null_mut();
+    let code = unsafe {libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)};
+    if code != 0 || result.is_null() {return None}
+    Some(passwd.pw_uid)
+}
+
 pub fn merge_toml(base: &mut Value, default: &Value) {
-    match (base,default) {
-        (Value::Table(basetable), Value::Table(deftable)) => {
-            for (key, defvalue) in deftable.iter() {
-                if ! basetable.contains_key(key) {basetable.insert(key.clone(), defvalue.clone());}
-                else {if let Some(baseval) = basetable.get_mut(key) {
-                    merge_toml(baseval, defvalue)
-                }}
+    if let (Value::Table(basetable), Value::Table(deftable)) = (base,default) {
+        for (key, defvalue) in deftable.iter() {
+            if ! basetable.contains_key(key) {basetable.insert(key.clone(), defvalue.clone());}
+            else if let Some(baseval) = basetable.get_mut(key) {
+                merge_toml(baseval, defvalue)
             }
-        },
-        _ => (),
+        }
     }
 }
 
@@ -52,6 +262,11 @@ pub fn merge_toml(base: &mut Value, default: &Value) {
 pub const ORCARC_DEFAULT : &str = "\
 maxproc = 4
 checkinterval = 10
+orcapath = \"orca\"
+backfill = false
+maxmem = 0
+killgrace = 30
+admins = []
 deleteafter = \"5d\"
 
 [defaultjob]
@@ -71,6 +286,8 @@ copyfiles = [
     \"_trj.xyz\",
     \".xyz\"
 ]
+conflict = \"overwrite\"
+pin = false
 
 [defaultjob.scheduling]
 priority = 1
@@ -78,7 +295,10 @@ maxtime = \"1h\"
 nprocs = 1
 restartpolicy = \"none\"
 maxrestart = 0
+after = []
+dependency = \"afterok\"
 
 [defaultjob.notify]
+events = [\"finished\", \"failed\", \"timeout\"]
 ";
 
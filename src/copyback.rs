@@ -0,0 +1,100 @@
+use crate::common::{jobs_fold, set_jobvalue, timestamp};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use toml::Value;
+
+// What to do when a result file already exists in the submission folder
+enum Conflict {
+    Overwrite,
+    Skip,
+    // Insert the job id before the suffix, e.g. h2.out -> h2.<id>.out
+    Rename,
+}
+
+impl Conflict {
+    fn from_name(name: &str) -> Option<Conflict> {
+        match name {
+            "overwrite" => Some(Conflict::Overwrite),
+            "skip" => Some(Conflict::Skip),
+            "rename" => Some(Conflict::Rename),
+            _ => None,
+        }
+    }
+}
+
+pub fn valid_conflict(name: &str) -> bool {
+    Conflict::from_name(name).is_some()
+}
+
+// Copies the files of <root>/jobs/<id> whose name ends with one of the
+// after.copyfiles suffixes back to the submission folder (result.path),
+// handling existing files according to after.conflict. On success the time of
+// the copy is recorded in result.copied. Returns the names of the copied files.
+pub fn copy_results(job: &str, jobtoml: &mut Value) -> io::Result<Vec<String>> {
+    let jobdir = jobs_fold().join(job);
+    let destination = match jobtoml.get("result").and_then(|r| r.get("path")).and_then(|v| v.as_str()) {
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing result.path in jobfile")),
+        Some(destination) => Path::new(destination).to_path_buf(),
+    };
+    let after = jobtoml.get("after");
+    let suffixes = after.and_then(|a| a.get("copyfiles"))
+        .and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|i| i.as_str().map(|s| s.to_string())).collect::<Vec<String>>())
+        .unwrap_or_default();
+    let conflict = after.and_then(|a| a.get("conflict"))
+        .and_then(|v| v.as_str())
+        .and_then(Conflict::from_name)
+        .unwrap_or(Conflict::Overwrite);
+
+    if !destination.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("Submission folder {} does not exist", destination.display())))
+    }
+
+    // The input and the files bundled with it are already in the submission
+    // folder, the bundled files are only copied back if orca wrote them
+    let launch = jobtoml.get("launch");
+    let input = launch.and_then(|l| l.get("input")).and_then(|v| v.as_str())
+        .and_then(|i| Path::new(i).file_name()).map(|n| n.to_string_lossy().to_string());
+    let bundled = launch.and_then(|l| l.get("bundled")).and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|i| i.as_str().map(|s| s.to_string())).collect::<Vec<String>>())
+        .unwrap_or_default();
+    let launched = jobtoml.get("result").and_then(|r| r.get("launched")).and_then(|v| v.as_integer()).unwrap_or(0) as u64;
+
+    let mut copied = vec![];
+    for entry in fs::read_dir(&jobdir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if input.as_ref() == Some(&name) {continue}
+        if bundled.contains(&name) && !modified_after(&entry.path(), launched) {continue}
+        // The longest matching suffix is used to build renamed files
+        let suffix = match suffixes.iter().filter(|s| name.ends_with(s.as_str())).max_by_key(|s| s.len()) {
+            None => continue,
+            Some(suffix) => suffix,
+        };
+        if !entry.path().is_file() {continue}
+
+        let mut target = destination.join(&name);
+        if target.exists() {
+            match conflict {
+                Conflict::Overwrite => (),
+                Conflict::Skip => continue,
+                Conflict::Rename => {
+                    let stem = name.strip_suffix(suffix.as_str()).unwrap_or(&name);
+                    target = destination.join(format!("{}.{}{}", stem, job, suffix));
+                },
+            }
+        }
+        fs::copy(entry.path(), &target)?;
+        copied.push(target.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(name));
+    }
+
+    set_jobvalue(jobtoml, "result", "copied", Value::Integer(timestamp() as i64));
+    Ok(copied)
+}
+
+fn modified_after(path: &Path, time: u64) -> bool {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .is_some_and(|m| m.as_secs() > time)
+}
@@ -1,163 +1,763 @@
-#![allow(unused_imports)]
+pub mod analysis;
 pub mod common;
+pub mod copyback;
+pub mod cleanup;
+pub mod notify;
+pub mod ipc;
+pub mod input;
+pub mod process;
+pub mod restart;
+pub mod store;
 
 
-use common::{JOBS_FILE, JOBS_LOCK, DONE_FILE, DONE_LOCK, WORK_FILE, WORK_LOCK, CONF_FILE, ORCARC_DEFAULT, JOBS_FOLD};
-use common::{acquire_lock,release_lock,merge_toml};
+use common::{conf_file, jobs_fold, stop_file, stop_lock, set_root};
+use common::{acquire_lock_wait, release_lock, read_orcarc, timestamp, read_jobfile, write_jobfile, set_jobvalue, read_ids, write_ids, job_nprocs, parse_duration, check_owner};
+use store::{JobRecord, State, Store, read_jobs, usedcores, usedmemory};
+use process::{signal_tree, is_alive, process_cwd, available_memory, total_memory};
+use copyback::copy_results;
+use analysis::{analyze, Summary};
+use notify::{notify, status_event};
+use ipc::{reply_error, reply_ok};
+use serde_json::json;
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self,BufReader, Seek, SeekFrom, Read, BufRead};
+use std::io;
 use std::path;
-use std::time::Duration;
-use toml;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
 use std::thread;
 use std::fs::File;
 
-fn read_config(path: &str) -> Config {
-    let confstr = match fs::read_to_string(path::Path::new(path)) {
-        Ok(content) => content,
-        Err(_) => {
-            // File does not exist
-            ORCARC_DEFAULT.to_string()
-        }
-    };
-    let default = ORCARC_DEFAULT.to_string().parse::<toml::Value>().unwrap();
-
-    let mut conf = match confstr.parse::<toml::Value>() {
-        Ok(conf) => conf,
-        // This line should never result in panic, the fixed values for orcarc
-        // should always work
-        Err(_) => ORCARC_DEFAULT.to_string().parse::<toml::Value>().unwrap(),
-    };
-    merge_toml(&mut conf, &default);
+fn read_config(path: &path::Path) -> Config {
+    let conf = read_orcarc(path);
 
     Config {
         maxproc: conf.get("maxproc").and_then(|v| v.as_integer()).unwrap_or(1) as usize,
         checkinterval: conf.get("checkinterval").and_then(|v| v.as_integer()).unwrap_or(30) as u64,
-        copyfiles: conf.get("copyfiles")
-                    .and_then(|v| v.as_array())
-                    .map(|a| {
-                        a.iter().filter_map(|i| i.as_str().map(|s| s.to_string())).collect::<Vec<String>>()
-                    }).unwrap_or_default(),
+        orcapath: conf.get("orcapath").and_then(|v| v.as_str()).unwrap_or("orca").to_string(),
+        backfill: conf.get("backfill").and_then(|v| v.as_bool()).unwrap_or(false),
+        // 0 leaves the memory unlimited, apart from the free memory of the system
+        maxmem: conf.get("maxmem").and_then(|v| v.as_integer()).filter(|m| *m > 0).map(|m| m as u64),
+        killgrace: conf.get("killgrace").and_then(|v| v.as_integer()).unwrap_or(30) as u64,
+        // An invalid value such as "never" disables the deletion
+        deleteafter: conf.get("deleteafter").and_then(|v| v.as_str()).and_then(parse_duration),
     }
 }
 
 struct Config {
     maxproc: usize,
-    copyfiles: Vec<String>,
     checkinterval: u64,
+    orcapath: String,
+    backfill: bool,
+    // Memory in MB that the running jobs may request in total
+    maxmem: Option<u64>,
+    killgrace: u64,
+    deleteafter: Option<u64>,
+}
+
+// Seconds between two garbage collections of the done jobs
+const GC_INTERVAL: u64 = 60 * 60;
+
+// A job launched by this daemon, or adopted from a previous one
+struct RunningJob {
+    // None for adopted jobs, which are not children of this daemon
+    child: Option<Child>,
+    pid: u32,
+    launched: u64,
+    maxtime: Option<u64>,
+    // Time at which the job was sent SIGTERM, and the status to record once it exits
+    stopping: Option<(u64, String)>,
+}
+impl RunningJob {
+    // Returns Some(exitcode) once the process has exited. The exit code is
+    // None if the process was killed by a signal or was not our child.
+    fn try_exit(&mut self) -> Option<Option<i32>> {
+        match &mut self.child {
+            Some(child) => match child.try_wait() {
+                Ok(Some(exitstatus)) => Some(exitstatus.code()),
+                _ => None,
+            },
+            None => if is_alive(self.pid) {None} else {Some(None)},
+        }
+    }
 }
 fn main() {
+    let matches = clap::Command::new("orcajobd").version("0.1.0")
+        .about("Runs the jobs queued with orcajob")
+        .arg(clap::arg!(root: --root <path> "The orcajob environment, defaults to $ORCAJOB_HOME, /var/lib/orcajob or $XDG_DATA_HOME/orcajob"))
+        .get_matches();
+    set_root(matches.get_one::<String>("root").map(|r| r.as_str()));
+
     // Load configuration
-    let config = read_config(CONF_FILE);
-    let mut cores = 0;
+    let config = read_config(&conf_file());
+    let mut running: HashMap<String, RunningJob> = HashMap::new();
+
+    if let Err(e) = fs::create_dir_all(jobs_fold()) {
+        eprintln!("Cannot create job folder {}: {}", jobs_fold().display(), e);
+        return;
+    }
+
+    if store::needs_migration() {
+        eprintln!("Found job lists from a previous version, they are ignored until orcajob migrate is run");
+    }
+
+    // Clients fall back to the job files if the socket is not available
+    let requests = match ipc::listen() {
+        Ok(requests) => Some(requests),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {eprintln!("{}", e); return},
+        Err(e) => {eprintln!("Cannot listen on {}, clients use the job files: {}", common::socket_file().display(), e); None},
+    };
 
-    println!("{}", read_second_to_last_line(".\\src\\daemon.rs").unwrap());
-    
     // Check for interrupted jobs
-    // TODO
+    if let Err(e) = recover_jobs(&mut running) {
+        eprintln!("Error while checking for interrupted jobs: {}", e);
+    }
+
+    let mut lastgc = 0;
 
     // Begin main loop
     loop {
         // Check for job completeness
-        completedjobs();
+        if let Err(e) = completedjobs(&mut running) {
+            eprintln!("Error while checking for completed jobs: {}", e);
+        }
 
-        // Check for available cores
-        cores = getusedcores();
+        // Check for stop requests and jobs over their time limit
+        if let Err(e) = stop_requested(&mut running) {
+            eprintln!("Error while reading stop requests: {}", e);
+        }
+        enforce_limits(&mut running, &config);
+
+        // Queue the jobs whose dependencies ended
+        match store::resolve_dependencies() {
+            Ok(cancelled) => for (job, reason) in cancelled {
+                println!("Job {} cancelled: {}", job, reason);
+                if let Ok((_, jobtoml)) = read_jobfile(&jobs_fold().join(&job)) {notify(&job, "cancelled", &jobtoml);}
+            },
+            Err(e) => eprintln!("Error while checking job dependencies: {}", e),
+        }
+
+        // Start new jobs as long as there are enough free cores and memory.
+        // The jobs started in this pass did not allocate their memory yet, so
+        // it is taken off the free memory of the system read before the pass.
+        // Running jobs may not have allocated all of theirs either, so the
+        // memory they requested is also taken off the total memory.
+        let systemmemory = available_memory();
+        let totalmemory = total_memory();
+        let mut startedmemory = 0;
+        loop {
+            // Check for available cores and memory
+            let jobs = match read_jobs() {
+                Ok(jobs) => jobs,
+                Err(e) => {eprintln!("Error while reading the job store: {}", e); break}
+            };
+            let mut free = Resources {
+                cores: config.maxproc.saturating_sub(usedcores(&jobs)),
+                memory: config.maxmem.map(|maxmem| maxmem.saturating_sub(usedmemory(&jobs))),
+            };
+            let systemfree = [
+                systemmemory.map(|m| m.saturating_sub(startedmemory)),
+                totalmemory.map(|m| m.saturating_sub(usedmemory(&jobs))),
+            ];
+            for systemfree in systemfree.into_iter().flatten() {
+                free.memory = Some(free.memory.map_or(systemfree, |m| m.min(systemfree)));
+            }
 
-        // Check for available jobs
-        let availablecores = config.maxproc - cores;
-        let job = get_new_job(availablecores);
-        
-        // Start new jobs
-        start_new_job(job);
+            // Check for available jobs
+            let job = match get_new_job(&free, &config) {
+                Err(e) => {eprintln!("Error while reading the job queue: {}", e); break},
+                Ok(None) => break,
+                Ok(Some(job)) => job,
+            };
+            startedmemory += job.memory;
+            let job = job.id;
 
-        // Sleep
-        thread::sleep(Duration::from_secs(config.checkinterval));
+            // Start new jobs
+            match start_new_job(&job, &config) {
+                Ok(runningjob) => {
+                    println!("Started job {} (pid {})", job, runningjob.pid);
+                    running.insert(job, runningjob);
+                },
+                Err(e) => {
+                    eprintln!("Cannot start job {}: {}", job, e);
+                    if let Err(e) = abort_job(&job, &e.to_string()) {
+                        eprintln!("Cannot abort job {}: {}", job, e);
+                    }
+                },
+            }
+        }
+
+        // Delete old job folders
+        if let Some(deleteafter) = config.deleteafter {
+            if timestamp().saturating_sub(lastgc) > GC_INTERVAL {
+                lastgc = timestamp();
+                match cleanup::collect_garbage(deleteafter, None, false) {
+                    Ok(collected) => for job in collected {println!("Deleted job {}", job.id)},
+                    Err(e) => eprintln!("Error while deleting old jobs: {}", e),
+                }
+            }
+        }
+
+        // Sleep until the next check, or until a client sends a request
+        let timeout = Duration::from_secs(config.checkinterval);
+        match &requests {
+            None => thread::sleep(timeout),
+            Some(requests) => wait_request(requests, timeout, &mut running, &config),
+        }
     }
 }
 
+// Answers the requests of the clients for at most timeout. Returns after the
+// first request that changes the queue, so that the main loop acts on it.
+fn wait_request(requests: &Receiver<ipc::Request>, timeout: Duration, running: &mut HashMap<String, RunningJob>, config: &Config) {
+    let deadline = Instant::now() + timeout;
+    while let Ok((request, reply)) = requests.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        let op = request.get("op").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let response = handle_request(&request, running, config).unwrap_or_else(|e| reply_error(&e));
+        let _ = reply.send(response);
+        if op != "status" {return}
+    }
+}
 
+fn handle_request(request: &serde_json::Value, running: &mut HashMap<String, RunningJob>, config: &Config) -> io::Result<serde_json::Value> {
+    let field = |key: &str| -> io::Result<String> {
+        request.get(key).and_then(|v| v.as_str()).map(|v| v.to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Missing {} in request", key)))
+    };
+    // Set by the listener to the user running the client
+    let user = field("user")?;
+    match field("op")?.as_str() {
+        "submit" => {
+            let job = field("id")?;
+            check_request_owner(&format!(".{}", job), &user)?;
+            let record = store::commit_job(&job)?;
+            println!("Job {} submitted by {}", record.id, record.user);
+            Ok(reply_ok(&record.id))
+        },
+        "stop" => {
+            let job = field("id")?;
+            check_request_owner(&job, &user)?;
+            Ok(reply_ok(&stop_job(&job, &user, running)?))
+        },
+        "hold" => {
+            let job = field("id")?;
+            check_request_owner(&job, &user)?;
+            store::hold_job(&job)?;
+            Ok(reply_ok(&format!("Job {} held", job)))
+        },
+        "release" => {
+            let job = field("id")?;
+            check_request_owner(&job, &user)?;
+            store::release_job(&job)?;
+            Ok(reply_ok(&format!("Job {} released", job)))
+        },
+        "requeue" => {
+            let job = field("id")?;
+            check_request_owner(&job, &user)?;
+            let requeues = store::requeue_job(&job)?;
+            println!("Job {} requeued by request, attempt {}", job, requeues + 1);
+            Ok(reply_ok(&format!("Job {} requeued", job)))
+        },
+        "status" => {
+            let jobs = read_jobs()?;
+            Ok(json!({
+                "ok": true,
+                "jobs": jobs,
+                "usedcores": usedcores(&jobs),
+                "maxproc": config.maxproc,
+                "usedmemory": usedmemory(&jobs),
+                "maxmem": config.maxmem.unwrap_or(0),
+            }))
+        },
+        op => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown operation {}", op))),
+    }
+}
 
-fn read_second_to_last_line(file_path: &str) -> io::Result<String> {
-    // ChatGPT TODO
-    // This code is not that good, it appears that for very long lines it might 
-    // encounter errors. As the orca files do not have very long lines I will ignore it
-    // for the time being. -_-
-    const BUFFER_SIZE: usize = 1024;
+// Refuses requests on the jobs of other users, unless the user is an admin.
+// The folder is the job id, or .<id> for a staged job.
+fn check_request_owner(folder: &str, user: &str) -> io::Result<()> {
+    if folder.is_empty() || folder.contains('/') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid job id {}", folder)))
+    }
+    let (_, jobtoml) = read_jobfile(&jobs_fold().join(folder))?;
+    check_owner(&jobtoml, user)
+}
 
-    // Open the file for reading and create a buffered reader
-    let file = File::open(file_path)?;
-    let mut reader = BufReader::new(file);
+// Classifies a finished run from its exit code and the summary of its output.
+// Returns the status to record (DONE or ERROR) and, for errors, the reason.
+fn check_job_complete(summary: &Summary, exitcode: Option<i32>) -> (String, Option<String>) {
+    if summary.terminated && exitcode.unwrap_or(0) == 0 {
+        return ("DONE".to_string(), None)
+    }
 
-    // Get the file size and calculate the starting position for reverse reading
-    let file_size = reader.seek(SeekFrom::End(0))?;
+    let reason = match (&summary.error, exitcode) {
+        (Some(banner), _) => banner.clone(),
+        (None, Some(code)) if code != 0 => format!("orca exited with code {}", code),
+        (None, _) => "The output does not contain the normal termination banner".to_string(),
+    };
+    ("ERROR".to_string(), Some(reason))
+}
 
-    if file_size == 0 {
-        // Empty file, no lines to read
-        return Err(io::Error::new(io::ErrorKind::NotFound, "File is empty"));
+// Records the summary of the output in the [summary] table of the job file
+fn record_summary(job: &str, summary: &Summary) -> io::Result<()> {
+    let (jobpath, mut jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    if let Some(jobtable) = jobtoml.as_table_mut() {
+        jobtable.insert("summary".to_string(), summary.to_toml());
     }
+    write_jobfile(&jobpath, &jobtoml)
+}
 
-    let mut pos = if file_size < BUFFER_SIZE as u64 {0}
-    else {file_size - BUFFER_SIZE as u64};
+fn job_output(job: &str) -> io::Result<path::PathBuf> {
+    let jobdir = jobs_fold().join(job);
+    let (_, jobtoml) = read_jobfile(&jobdir)?;
+    Ok(jobdir.join(launch_filename(&jobtoml, "output")?))
+}
 
-    let mut buffer = [0; BUFFER_SIZE];
+// Reaps the jobs whose process has exited, records their exit code, end time
+// and status, then restarts or finalizes them
+fn completedjobs(running: &mut HashMap<String, RunningJob>) -> io::Result<()>{
+    let mut exited = vec![];
+    for (id, job) in running.iter_mut() {
+        if let Some(exitcode) = job.try_exit() {
+            exited.push((id.clone(), job.stopping.clone(), exitcode));
+        }
+    }
 
-    loop {
-        // Seek to the calculated position and read a chunk of data into the buffer
-        reader.seek(SeekFrom::Start(pos))?;
-        let bytes_read = reader.read(&mut buffer)?;
-
-        // Iterate through the buffer in reverse, looking for two newline characters
-        for i in (0..bytes_read).rev() {
-            if buffer[i] == b'\n' {
-                // We found the last newline character, now look for the second one
-                for j in (0..i).rev() {
-                    if buffer[j] == b'\n' {
-                        // We found the second-to-last newline character
-                        // Extract the line between the two newline characters and return it
-                        let mut line = Vec::with_capacity(i - j + 1);
-                        line.extend_from_slice(&buffer[j + 1..i + 1]);
-                        return String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
-                    }
+    for (id, stopping, exitcode) in exited {
+        running.remove(&id);
+        let summary = job_output(&id).and_then(|outpath| analyze(&outpath));
+        let (status, reason) = match (stopping, &summary) {
+            // Jobs stopped by the daemon keep the status they were stopped with
+            (Some((_, status)), _) => (status, None),
+            (None, Ok(summary)) => check_job_complete(summary, exitcode),
+            (None, Err(e)) => ("ERROR".to_string(), Some(e.to_string())),
+        };
+        if let Some(Err(e)) = summary.as_ref().ok().map(|summary| record_summary(&id, summary)) {
+            eprintln!("Cannot record the summary of job {}: {}", id, e);
+        }
+        match &reason {
+            None => println!("Job {} ended with status {}", id, status),
+            Some(reason) => println!("Job {} ended with status {}: {}", id, status, reason),
+        }
+        if let Err(e) = end_job(&id, &status, reason.as_deref(), exitcode) {
+            eprintln!("Cannot finalize job {}: {}", id, e);
+        }
+    }
+    Ok(())
+}
+
+// Free cores and memory in MB, the memory is None if it is not limited
+struct Resources {
+    cores: usize,
+    memory: Option<u64>,
+}
+
+// Checks whether a job can ever run with the limits of orcarc
+fn within_limits(job: &JobRecord, config: &Config) -> bool {
+    job.nprocs <= config.maxproc && config.maxmem.is_none_or(|maxmem| job.memory <= maxmem)
+}
+
+// Selects the next job to run: jobs with a higher priority go first, then the
+// ones scheduled earlier. If the first job does not fit in the free resources
+// and backfill is enabled, the first smaller job that fits is selected instead.
+// Jobs that can never fit in maxproc or maxmem are selected right away, so that
+// start_new_job refuses them instead of blocking the queue.
+fn select_job(queue: &[JobRecord], free: &Resources, config: &Config) -> Option<String> {
+    if let Some(job) = queue.iter().find(|j| !within_limits(j, config)) {
+        return Some(job.id.clone())
+    }
+    let mut jobs = queue.iter().collect::<Vec<&JobRecord>>();
+    jobs.sort_by(|a, b| b.priority.cmp(&a.priority).then(a.scheduled.cmp(&b.scheduled)));
+
+    let fits = |j: &&&JobRecord| j.nprocs <= free.cores && free.memory.is_none_or(|m| j.memory <= m);
+    match jobs.first() {
+        None => None,
+        Some(head) if fits(&head) => Some(head.id.clone()),
+        Some(_) if config.backfill => jobs.iter().find(fits).map(|j| j.id.clone()),
+        Some(_) => None,
+    }
+}
+
+// Takes the next job from the queue, if any fits in the free resources, and
+// marks it as running
+fn get_new_job(free: &Resources, config: &Config) -> io::Result<Option<JobRecord>> {
+    let store = Store::open()?;
+    let queue = store.jobs()?.into_iter().filter(|j| j.state == State::Queued).collect::<Vec<JobRecord>>();
+    let job = match select_job(&queue, free, config) {
+        None => return Ok(None),
+        Some(job) => job,
+    };
+    Ok(Some(store.update(&job, &[State::Queued], |r| r.state = State::Running)?))
+}
+
+// Returns the file name of the launch.input or launch.output path, which
+// compile_job records in the submission folder
+fn launch_filename(jobtoml: &toml::Value, key: &str) -> io::Result<String> {
+    jobtoml.get("launch")
+        .and_then(|l| l.get(key))
+        .and_then(|v| v.as_str())
+        .and_then(|p| path::Path::new(p).file_name())
+        .map(|f| f.to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Missing launch.{} in jobfile", key)))
+}
+
+// Launches orca in the job folder on the copy of the input file, redirecting
+// the output to the output file recorded by compile_job
+fn start_new_job(job: &str, config: &Config) -> io::Result<RunningJob> {
+    let jobdir = jobs_fold().join(job);
+    let (jobpath, mut jobtoml) = read_jobfile(&jobdir)?;
+
+    let input = launch_filename(&jobtoml, "input")?;
+    let output = launch_filename(&jobtoml, "output")?;
+
+    let nprocs = job_nprocs(job)?;
+    if nprocs > config.maxproc {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job requires {} cores but maxproc is {}", nprocs, config.maxproc)))
+    }
+    let memory = jobtoml.get("scheduling").and_then(|s| s.get("memory")).and_then(|v| v.as_integer()).unwrap_or(0) as u64;
+    if let Some(maxmem) = config.maxmem.filter(|maxmem| memory > *maxmem) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job requires {} MB but maxmem is {} MB", memory, maxmem)))
+    }
+
+    copy_deferred(&jobtoml, &jobdir)?;
+
+    let outfile = File::create(jobdir.join(output))?;
+    let errfile = outfile.try_clone()?;
+
+    // The job runs in its own process group, so that the whole tree, including
+    // the MPI processes, can be signalled at once
+    let mut child = Command::new(&config.orcapath)
+        .arg(input)
+        .current_dir(&jobdir)
+        .stdin(Stdio::null())
+        .stdout(outfile)
+        .stderr(errfile)
+        .process_group(0)
+        .spawn()?;
+    let launched = timestamp();
+    let pid = child.id();
+
+    // A job that is not recorded as running would never be reaped, so it is
+    // killed if it cannot be recorded
+    set_jobvalue(&mut jobtoml, "result", "launched", toml::Value::Integer(launched as i64));
+    set_jobvalue(&mut jobtoml, "result", "pid", toml::Value::Integer(pid as i64));
+    let recorded = write_jobfile(&jobpath, &jobtoml)
+        .and_then(|_| Store::open()?.update(job, &[State::Running], |r| r.launched = launched));
+    if let Err(e) = recorded {
+        if let Err(e) = signal_tree(pid, libc::SIGKILL) {eprintln!("Cannot kill job {}: {}", job, e);}
+        let _ = child.wait();
+        return Err(e)
+    }
+    notify(job, "started", &jobtoml);
+
+    Ok(RunningJob { child: Some(child), pid, launched, maxtime: job_maxtime(&jobtoml, job), stopping: None })
+}
+
+// Copies the files written by the dependencies of a job or by the earlier
+// steps of its pipeline, see compile_job and schedule_pipeline
+fn copy_deferred(jobtoml: &toml::Value, jobdir: &path::Path) -> io::Result<()> {
+    let deferred = jobtoml.get("launch").and_then(|l| l.get("deferred")).and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    for file in deferred.iter() {
+        let field = |key: &str| file.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let (source, name) = (field("source"), field("name"));
+        if name.is_empty() || path::Path::new(&name).file_name() != Some(std::ffi::OsStr::new(&name)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Invalid deferred file {} in jobfile", name)))
+        }
+        let cannot_copy = |e: io::Error| io::Error::new(e.kind(), format!("Cannot copy {}: {}", source, e));
+        if field("frame") == "last" {
+            let content = fs::read_to_string(&source).map_err(cannot_copy)?;
+            let frame = last_xyz_frame(&content)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No geometry found in {}", source)))?;
+            fs::write(jobdir.join(&name), frame)?;
+        } else {
+            fs::copy(&source, jobdir.join(&name)).map_err(cannot_copy)?;
+        }
+    }
+    Ok(())
+}
+
+// Returns the last geometry of an xyz trajectory, whose frames each start
+// with the number of atoms and a comment line
+fn last_xyz_frame(content: &str) -> Option<String> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let mut index = 0;
+    let mut last = None;
+    while let Some(natoms) = lines.get(index).and_then(|l| l.trim().parse::<usize>().ok()) {
+        let end = index + 2 + natoms;
+        if end > lines.len() {break}
+        last = Some(lines[index..end].join("\n") + "\n");
+        index = end;
+    }
+    last
+}
+
+fn job_maxtime(jobtoml: &toml::Value, job: &str) -> Option<u64> {
+    match jobtoml.get("scheduling").and_then(|s| s.get("maxtime")).and_then(|v| v.as_str()) {
+        None => None,
+        Some(maxtime) => match parse_duration(maxtime) {
+            None => {eprintln!("Invalid maxtime {} for job {}, running without time limit", maxtime, job); None},
+            Some(maxtime) => Some(maxtime),
+        }
+    }
+}
+
+// Sends SIGTERM to the jobs that exceeded their maxtime, and SIGKILL to the
+// ones still alive killgrace seconds after SIGTERM
+fn enforce_limits(running: &mut HashMap<String, RunningJob>, config: &Config) {
+    let now = timestamp();
+    for (id, job) in running.iter_mut() {
+        let pid = job.pid;
+        match &job.stopping {
+            None => {
+                if job.maxtime.is_some_and(|maxtime| now.saturating_sub(job.launched) > maxtime) {
+                    println!("Job {} exceeded its maxtime, terminating", id);
+                    if let Err(e) = signal_tree(pid, libc::SIGTERM) {eprintln!("Cannot terminate job {}: {}", id, e);}
+                    job.stopping = Some((now, "TIMEOUT".to_string()));
                 }
-            }
+            },
+            Some((since, _)) => {
+                if now.saturating_sub(*since) > config.killgrace {
+                    println!("Job {} did not terminate, killing", id);
+                    if let Err(e) = signal_tree(pid, libc::SIGKILL) {eprintln!("Cannot kill job {}: {}", id, e);}
+                }
+            },
         }
+    }
+}
 
-        if pos == 0 {
-            // We reached the beginning of the file, break the loop
-            break;
+// Handles the stop requests written by orcajob stop when the daemon was not
+// listening
+fn stop_requested(running: &mut HashMap<String, RunningJob>) -> io::Result<()> {
+    let stoplock = acquire_lock_wait(&stop_lock())?;
+    let requests = read_ids(&stop_file()).and_then(|r| {write_ids(&stop_file(), &[])?; Ok(r)});
+    release_lock(&stoplock)?;
+
+    for request in requests? {
+        let (job, user) = request.split_once(' ').unwrap_or((&request, "notset"));
+        match stop_job(job, user, running) {
+            Ok(message) => println!("{}", message),
+            Err(e) => eprintln!("Cannot stop job {}: {}", job, e),
         }
+    }
+    Ok(())
+}
 
-        // Move the position backwards to read the next chunk
-        pos = if pos < BUFFER_SIZE as u64 {
-            0
-        } else {
-            pos - BUFFER_SIZE as u64
-        };
+// Stops a job as requested by user: queued jobs are cancelled, running jobs
+// are sent SIGTERM and will be killed by enforce_limits after the grace period
+fn stop_job(job: &str, user: &str, running: &mut HashMap<String, RunningJob>) -> io::Result<String> {
+    if store::cancel_queued(job, user)? {
+        return Ok(format!("Job {} cancelled", job))
     }
 
-    // If we reach this point, we didn't find two newline characters in the entire file
-    Err(io::Error::new(io::ErrorKind::NotFound, "Two newline characters not found"))
+    match running.get_mut(job) {
+        Some(runningjob) => {
+            if runningjob.stopping.is_none() {
+                if let Err(e) = record_stoppedby(job, user) {eprintln!("Cannot record who stopped job {}: {}", job, e);}
+                println!("Stopping job {} as requested by {}", job, user);
+                if let Err(e) = signal_tree(runningjob.pid, libc::SIGTERM) {eprintln!("Cannot terminate job {}: {}", job, e);}
+                runningjob.stopping = Some((timestamp(), "CANCELLED".to_string()));
+            }
+            Ok(format!("Stop requested for job {}", job))
+        },
+        None => {
+            // The job was marked as running but was not started
+            let inwork = read_jobs()?.iter().any(|j| j.id == job && j.state == State::Running);
+            if !inwork {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} has already ended", job)))
+            }
+            if let Err(e) = record_stoppedby(job, user) {eprintln!("Cannot record who stopped job {}: {}", job, e);}
+            finish_job(job, "CANCELLED", None, None)?;
+            Ok(format!("Job {} cancelled", job))
+        },
+    }
 }
-fn check_job_complete(jobpath: path::PathBuf) -> io::Result<bool> {
-    Ok(false)
+
+// Records the user who stopped a job in its result table
+fn record_stoppedby(job: &str, user: &str) -> io::Result<()> {
+    let (jobpath, mut jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    set_jobvalue(&mut jobtoml, "result", "stoppedby", toml::Value::String(user.to_string()));
+    write_jobfile(&jobpath, &jobtoml)
 }
 
-fn completedjobs() -> io::Result<()>{
-    // let lock = acquire_lock(WORK_LOCK)?;
-    // let inprogress = BufReader::new(work);
+// Checks the restartpolicy of a job that ended with the given status.
+// Policies are "none", "onfailure" and "always", and a job is restarted at
+// most scheduling.maxrestart times.
+fn should_restart(jobtoml: &toml::Value, status: &str) -> bool {
+    if status == "CANCELLED" {return false}
+    let scheduling = jobtoml.get("scheduling");
+    let policy = scheduling.and_then(|s| s.get("restartpolicy")).and_then(|v| v.as_str()).unwrap_or("none");
+    let maxrestart = scheduling.and_then(|s| s.get("maxrestart")).and_then(|v| v.as_integer()).unwrap_or(0);
+    let restarts = jobtoml.get("result").and_then(|r| r.get("restarts")).and_then(|v| v.as_integer()).unwrap_or(0);
 
-    // for job in inprogress.lines() {
-    //     if let Ok(job) = job {
-    //         let jobdir = path::Path::new(JOBS_FOLD).join(job);
-    //     }
-    // }
-    // release_lock(&lock)?;
+    restarts < maxrestart && match policy {
+        "always" => true,
+        "onfailure" => status != "DONE",
+        _ => false,
+    }
+}
+
+// Handles the end of a job run, either restarting it according to its
+// restartpolicy or finalizing it
+fn end_job(job: &str, status: &str, reason: Option<&str>, exitcode: Option<i32>) -> io::Result<()> {
+    let jobdir = jobs_fold().join(job);
+    if let Ok((_, jobtoml)) = read_jobfile(&jobdir) {
+        if should_restart(&jobtoml, status) {
+            return restart_job(job, status)
+        }
+    }
+    finish_job(job, status, reason, exitcode)
+}
+
+// Puts a job back in the queue, keeping the output of the previous run as
+// <output>.<n>. With the onfailure policy the input is updated to start from
+// the latest orbitals and geometry.
+fn restart_job(job: &str, status: &str) -> io::Result<()> {
+    let jobdir = jobs_fold().join(job);
+    let (jobpath, mut jobtoml) = read_jobfile(&jobdir)?;
+    let input = launch_filename(&jobtoml, "input")?;
+    let output = launch_filename(&jobtoml, "output")?;
+    let restarts = jobtoml.get("result").and_then(|r| r.get("restarts")).and_then(|v| v.as_integer()).unwrap_or(0) + 1;
+
+    if jobdir.join(&output).exists() {
+        fs::rename(jobdir.join(&output), jobdir.join(format!("{}.{}", output, restarts)))?;
+    }
+    let policy = jobtoml.get("scheduling").and_then(|s| s.get("restartpolicy")).and_then(|v| v.as_str()).unwrap_or("none");
+    if policy == "onfailure" {
+        let used = restart::patch_input(&jobdir, &input)?;
+        if !used.is_empty() {println!("Job {} restarts from {}", job, used.join(", "));}
+    }
+
+    if let Some(result) = jobtoml.get_mut("result").and_then(|r| r.as_table_mut()) {
+        for key in ["launched", "ended", "pid", "status", "reason", "exitcode"] {
+            result.remove(key);
+        }
+    }
+    if let Some(jobtable) = jobtoml.as_table_mut() {jobtable.remove("summary");}
+    set_jobvalue(&mut jobtoml, "result", "restarts", toml::Value::Integer(restarts));
+    set_jobvalue(&mut jobtoml, "result", "laststatus", toml::Value::String(status.to_string()));
+    write_jobfile(&jobpath, &jobtoml)?;
+
+    requeue_job(job)?;
+    notify(job, "restarted", &jobtoml);
+
+    println!("Job {} requeued, restart {}", job, restarts);
+    Ok(())
+}
+
+// Puts a running job back in the queue
+fn requeue_job(job: &str) -> io::Result<()> {
+    Store::open()?.update(job, &[State::Running], |r| {
+        r.state = State::Queued;
+        r.launched = 0;
+    })?;
+    Ok(())
+}
+
+// Inspects the jobs left running by a previous daemon. Jobs whose
+// orca process is still alive are adopted, the others are restarted according
+// to their restartpolicy or marked as FAILED.
+fn recover_jobs(running: &mut HashMap<String, RunningJob>) -> io::Result<()> {
+    let ids = read_jobs()?.into_iter().filter(|j| j.state == State::Running).map(|j| j.id);
+
+    for job in ids {
+        match recover_job(&job) {
+            Err(e) => eprintln!("Cannot recover job {}: {}", job, e),
+            Ok(None) => (),
+            Ok(Some(runningjob)) => {
+                println!("Adopted job {} (pid {})", job, runningjob.pid);
+                running.insert(job, runningjob);
+            },
+        }
+    }
     Ok(())
 }
 
-fn getusedcores() -> usize {0}
+fn recover_job(job: &str) -> io::Result<Option<RunningJob>> {
+    let jobdir = jobs_fold().join(job);
+    let jobtoml = match read_jobfile(&jobdir) {
+        Err(e) => {
+            finish_job(job, "FAILED", Some(&format!("Interrupted, cannot read job file: {}", e)), None)?;
+            return Ok(None)
+        },
+        Ok((_, jobtoml)) => jobtoml,
+    };
+    let result = jobtoml.get("result");
+    let pid = result.and_then(|r| r.get("pid")).and_then(|v| v.as_integer()).map(|p| p as u32);
+    let launched = result.and_then(|r| r.get("launched")).and_then(|v| v.as_integer()).unwrap_or(0) as u64;
+
+    let pid = match pid {
+        None => {
+            // The job was marked as running but never started
+            println!("Job {} was never started, requeueing", job);
+            requeue_job(job)?;
+            return Ok(None)
+        },
+        Some(pid) => pid,
+    };
+
+    // The pid may have been reused by an unrelated process after a reboot
+    let ownprocess = fs::canonicalize(&jobdir).ok().is_some_and(|dir| process_cwd(pid) == Some(dir));
+    if is_alive(pid) && ownprocess {
+        return Ok(Some(RunningJob { child: None, pid, launched, maxtime: job_maxtime(&jobtoml, job), stopping: None }))
+    }
 
-fn get_new_job(ncores: usize) -> String {"a".to_string()}
+    // Without any banner the run was cut short, e.g. by a reboot
+    let outpath = jobdir.join(launch_filename(&jobtoml, "output")?);
+    let summary = analyze(&outpath).unwrap_or_default();
+    if let Err(e) = record_summary(job, &summary) {
+        eprintln!("Cannot record the summary of job {}: {}", job, e);
+    }
+    match (summary.terminated, &summary.error) {
+        (false, None) => {
+            println!("Job {} was interrupted", job);
+            end_job(job, "FAILED", Some("Interrupted while the daemon was not running"), None)?;
+        },
+        _ => {
+            let (status, reason) = check_job_complete(&summary, None);
+            println!("Job {} ended with status {} while the daemon was not running", job, status);
+            end_job(job, &status, reason.as_deref(), None)?;
+        },
+    }
+    Ok(None)
+}
 
-fn start_new_job(job: String) {}
\ No newline at end of file
+// Records the end of a job in its job file, copies its results back to the
+// submission folder and marks it as done in the job store
+fn finish_job(job: &str, status: &str, reason: Option<&str>, exitcode: Option<i32>) -> io::Result<()> {
+    let ended = timestamp();
+    let jobdir = jobs_fold().join(job);
+    if let Ok((jobpath, mut jobtoml)) = read_jobfile(&jobdir) {
+        // Jobs that never ran have nothing to copy
+        if jobtoml.get("result").and_then(|r| r.get("launched")).is_some() {
+            match copy_results(job, &mut jobtoml) {
+                Ok(copied) => println!("Copied {} result files of job {}", copied.len(), job),
+                Err(e) => eprintln!("Cannot copy the results of job {}: {}", job, e),
+            }
+        }
+        set_jobvalue(&mut jobtoml, "result", "ended", toml::Value::Integer(ended as i64));
+        set_jobvalue(&mut jobtoml, "result", "status", toml::Value::String(status.to_string()));
+        if let Some(reason) = reason {
+            set_jobvalue(&mut jobtoml, "result", "reason", toml::Value::String(reason.to_string()));
+        }
+        if let Some(exitcode) = exitcode {
+            set_jobvalue(&mut jobtoml, "result", "exitcode", toml::Value::Integer(exitcode as i64));
+        }
+        write_jobfile(&jobpath, &jobtoml)?;
+        notify(job, status_event(status), &jobtoml);
+    }
+
+    Store::open()?.update(job, &[State::Running], |r| {
+        r.state = State::Done;
+        r.ended = ended;
+        r.status = status.to_string();
+    })?;
+    Ok(())
+}
+
+// Marks a job that could not be launched as failed. The job keeps no launch
+// time, so that finish_job does not copy back results it never produced.
+fn abort_job(job: &str, reason: &str) -> io::Result<()> {
+    finish_job(job, "FAILED", Some(reason), None)
+}
@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Blocks written on a single line, without end, e.g. %maxcore 3000
+const LINE_BLOCKS: [&str; 4] = ["maxcore", "moinp", "base", "pointcharges"];
+
+// Keywords that open a sub-block closed by its own end inside a block, e.g.
+// the constraints of %geom or the basis sets of %basis. Their content is not
+// read as entries of the block. Other sub-blocks are tolerated, see parse_block.
+const SUB_BLOCKS: [&str; 17] = [
+    "constraints", "scan", "coords", "pardef", "pars", "fragments", "hybrid_hess",
+    "newgto", "newauxgto", "newauxjgto", "newauxjkgto", "newauxcgto", "newecp", "addgto",
+    "rotate", "modify_internal", "newblock",
+];
+
+// Block entries whose value is the name of a file read by orca. Quoted values
+// of other entries are bundled too when they name an existing file.
+const FILE_KEYS: [&str; 17] = [
+    "inhessname", "gtoname", "auxgtoname", "auxjgtoname", "auxjkgtoname", "auxcgtoname",
+    "cabsgtoname", "ecpname", "xyzfile", "gbwname", "neb_end_xyzfile", "neb_ts_xyzfile",
+    "restart_allxyzfile", "gshessian", "eshessian", "orcafffilename", "guest",
+];
+
+// A %name ... end block, or a single line block such as %maxcore
+#[derive(Debug, Clone)]
+pub struct Block {
+    // Lowercase name without the %
+    pub name: String,
+    // Lowercase key and value as written of each entry, sub-blocks are not
+    // included
+    pub entries: Vec<(String, String)>,
+    // Value of a single line block
+    pub value: Option<String>,
+    pub line: usize,
+}
+
+impl Block {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+// The coordinates of a * xyz ... * block, or of a * xyzfile line
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    // Lowercase type, e.g. xyz, int, gzmt, xyzfile or gzmtfile
+    pub kind: String,
+    pub charge: i64,
+    pub mult: i64,
+    pub file: Option<String>,
+    pub line: usize,
+    // Line of the closing *, the same as line for the file types
+    pub end: usize,
+}
+
+// One job of the input, the input holds several when they are separated by $new_job
+#[derive(Debug, Clone, Default)]
+pub struct Step {
+    // Simple keywords of the ! lines, as written
+    pub keywords: Vec<String>,
+    pub blocks: Vec<Block>,
+    pub geometry: Option<Geometry>,
+}
+
+impl Step {
+    pub fn block(&self, name: &str) -> Option<&Block> {
+        self.blocks.iter().rev().find(|b| b.name == name)
+    }
+
+    // Number of processes, from %pal nprocs or a PALn keyword
+    pub fn nprocs(&self) -> Option<i64> {
+        if let Some(nprocs) = self.block("pal").and_then(|b| b.get("nprocs")).and_then(|v| v.parse().ok()) {
+            return Some(nprocs)
+        }
+        let keywords = self.keywords.iter().map(|k| k.to_lowercase()).collect::<Vec<String>>();
+        for (index, keyword) in keywords.iter().enumerate() {
+            if let Some(n) = keyword.strip_prefix("pal").and_then(|n| n.parse().ok()) {return Some(n)}
+            // PAL 8 is accepted as well as PAL8
+            if keyword == "pal" {
+                if let Some(n) = keywords.get(index + 1).and_then(|n| n.parse().ok()) {return Some(n)}
+            }
+        }
+        None
+    }
+
+    // Memory per core in MB, from %maxcore
+    pub fn maxcore(&self) -> Option<i64> {
+        self.block("maxcore").and_then(|b| b.value.as_deref()).and_then(|v| v.parse().ok())
+    }
+
+    // Files read by orca in this step, as written in the input: coordinate
+    // files, orbital guesses, point charges, hessians and basis set files
+    pub fn referenced_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = vec![];
+        if let Some(file) = self.geometry.as_ref().and_then(|g| g.file.clone()) {files.push(file);}
+        for block in self.blocks.iter() {
+            if let (true, Some(value)) = (block.name == "moinp" || block.name == "pointcharges", &block.value) {
+                files.push(value.clone());
+            }
+            for (key, value) in block.entries.iter() {
+                if FILE_KEYS.contains(&key.as_str()) {files.push(value.clone());}
+            }
+        }
+        files
+    }
+}
+
+// The files to copy in the job folder for an input
+pub struct Bundle {
+    // Content of the input, with the references rewritten to the job folder
+    pub input: String,
+    // Path of each referenced file and its name in the job folder
+    pub files: Vec<(PathBuf, String)>,
+    // Referenced files that do not exist yet, to copy when the job starts
+    pub deferred: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OrcaInput {
+    pub steps: Vec<Step>,
+}
+
+// A token of a line and its byte range in the line, quotes included
+struct Token {
+    text: String,
+    span: Range<usize>,
+    quoted: bool,
+}
+
+// Splits a line in tokens, dropping the comments. A # starts a comment that
+// ends at the next # or at the end of the line. Quoted strings are single
+// tokens, returned without their quotes.
+fn tokenize_spans(line: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut token: Option<Token> = None;
+    let mut quoted = false;
+    let mut comment = false;
+    for (position, c) in line.char_indices() {
+        let end = position + c.len_utf8();
+        if comment {
+            if c == '#' {comment = false;}
+            continue;
+        }
+        match c {
+            '"' => {
+                if quoted {
+                    let mut quoted_token = token.take().unwrap_or(Token { text: String::new(), span: position..end, quoted: true });
+                    quoted_token.span.end = end;
+                    tokens.push(quoted_token);
+                } else {
+                    tokens.extend(token.take());
+                    token = Some(Token { text: String::new(), span: position..end, quoted: true });
+                }
+                quoted = !quoted;
+            },
+            _ if quoted => {
+                if let Some(token) = token.as_mut() {token.text.push(c);}
+            },
+            '#' => {
+                tokens.extend(token.take());
+                comment = true;
+            },
+            // ORCA accepts key = value as well as key value
+            c if c.is_whitespace() || c == '=' || c == ',' => tokens.extend(token.take()),
+            c => {
+                let token = token.get_or_insert(Token { text: String::new(), span: position..end, quoted: false });
+                token.text.push(c);
+                token.span.end = end;
+            },
+        }
+    }
+    // An unclosed quote runs to the end of the line
+    tokens.extend(token.filter(|t| !t.text.is_empty()));
+    tokens
+}
+
+fn tokenize(line: &str) -> Vec<String> {
+    tokenize_spans(line).into_iter().map(|t| t.text).collect()
+}
+
+fn parse_error(line: usize, message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line, message))
+}
+
+fn parse_integer(token: Option<&String>, what: &str, line: usize) -> io::Result<i64> {
+    token.and_then(|t| t.parse().ok()).ok_or_else(|| parse_error(line, format!("invalid {} in coordinates", what)))
+}
+
+impl OrcaInput {
+    pub fn read(path: &Path) -> io::Result<OrcaInput> {
+        let content = fs::read_to_string(path)?;
+        OrcaInput::parse(&content).map_err(|e| io::Error::new(e.kind(), format!("Error in {}, {}", path.display(), e)))
+    }
+
+    pub fn parse(content: &str) -> io::Result<OrcaInput> {
+        let lines = content.lines().map(tokenize).collect::<Vec<Vec<String>>>();
+        let mut input = OrcaInput { steps: vec![Step::default()] };
+        let mut index = 0;
+        while index < lines.len() {
+            let lineno = index + 1;
+            let tokens = &lines[index];
+            index += 1;
+            let first = match tokens.first() {
+                None => continue,
+                Some(first) => first,
+            };
+            let step = input.steps.last_mut().unwrap();
+
+            if first.to_lowercase() == "$new_job" {
+                input.steps.push(Step::default());
+            } else if let Some(keyword) = first.strip_prefix('!') {
+                if !keyword.is_empty() {step.keywords.push(keyword.to_string());}
+                step.keywords.extend(tokens[1..].iter().cloned());
+            } else if let Some(kind) = first.strip_prefix('*') {
+                // * xyz 0 1 and *xyz 0 1 are both valid
+                let mut words = tokens[1..].to_vec();
+                if !kind.is_empty() {words.insert(0, kind.to_string());}
+                let kind = words.first().map(|k| k.to_lowercase())
+                    .ok_or_else(|| parse_error(lineno, "missing coordinate type after *".to_string()))?;
+                let charge = parse_integer(words.get(1), "charge", lineno)?;
+                let mult = parse_integer(words.get(2), "multiplicity", lineno)?;
+                let file = if kind.ends_with("file") {
+                    Some(words.get(3).cloned().ok_or_else(|| parse_error(lineno, format!("missing file name after * {}", kind)))?)
+                } else {
+                    // The coordinates end at a line holding only *
+                    match lines[index..].iter().position(|l| l.len() == 1 && l[0] == "*") {
+                        None => return Err(parse_error(lineno, "coordinates are not closed by *".to_string())),
+                        Some(end) => index += end + 1,
+                    }
+                    None
+                };
+                step.geometry = Some(Geometry { kind, charge, mult, file, line: lineno, end: index });
+            } else if let Some(name) = first.strip_prefix('%') {
+                let mut words = tokens.clone();
+                let name = match name {
+                    // % pal is accepted as well as %pal
+                    "" if words.len() > 1 => words.remove(1).to_lowercase(),
+                    "" => return Err(parse_error(lineno, "missing block name after %".to_string())),
+                    name => name.to_lowercase(),
+                };
+                words.remove(0);
+
+                if LINE_BLOCKS.contains(&name.as_str()) {
+                    step.blocks.push(Block { name, entries: vec![], value: Some(words.join(" ")), line: lineno });
+                    continue;
+                }
+                let (block, next) = parse_block(&lines, name, words, index)?;
+                step.blocks.push(block);
+                index = next;
+            } else {
+                return Err(parse_error(lineno, format!("unexpected {}", first)))
+            }
+        }
+        Ok(input)
+    }
+
+    // Largest number of processes requested by a step, None if the input
+    // runs serially
+    pub fn nprocs(&self) -> Option<i64> {
+        self.steps.iter().filter_map(|s| s.nprocs()).max()
+    }
+
+    // Largest memory per core in MB requested by a step
+    pub fn maxcore(&self) -> Option<i64> {
+        self.steps.iter().filter_map(|s| s.maxcore()).max()
+    }
+
+    // Charge and multiplicity of the first step, from its coordinates or its
+    // %coords block
+    pub fn charge_mult(&self) -> Option<(i64, i64)> {
+        let step = self.steps.first()?;
+        if let Some(geometry) = &step.geometry {return Some((geometry.charge, geometry.mult))}
+        let coords = step.block("coords")?;
+        Some((coords.get("charge")?.parse().ok()?, coords.get("mult")?.parse().ok()?))
+    }
+
+    // Files read by orca in any step, as written in the input
+    pub fn referenced_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = vec![];
+        for file in self.steps.iter().flat_map(|s| s.referenced_files()) {
+            if !files.contains(&file) {files.push(file);}
+        }
+        files
+    }
+
+    // Resolves the files read by the input at inputfile against its folder and
+    // rewrites the input to read them from the job folder, where they are
+    // copied under their file name. Files missing from the later steps are
+    // accepted if they are plain names, since an earlier step may write them.
+    // The .gbw of the input is bundled too if it exists, orca reads it to
+    // restart the calculation. With defer, missing files are copied when the
+    // job starts instead, for jobs that wait for others to write them.
+    pub fn bundle(&self, inputfile: &Path, defer: bool) -> io::Result<Bundle> {
+        let dir = inputfile.parent().unwrap_or(Path::new("."));
+        let inputname = inputfile.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut files: Vec<(PathBuf, String)> = vec![];
+        let mut deferred: Vec<(PathBuf, String)> = vec![];
+        let mut renames: HashMap<String, String> = HashMap::new();
+
+        let content = fs::read_to_string(inputfile)?;
+        let mut references = vec![];
+        for (index, step) in self.steps.iter().enumerate() {
+            references.extend(step.referenced_files().into_iter().map(|file| (index, file)));
+        }
+        for file in quoted_values(&content) {
+            let source = dir.join(&file);
+            if source.is_file() && source.file_name() != inputfile.file_name() && !references.iter().any(|(_, r)| r == &file) {
+                references.push((0, file));
+            }
+        }
+        for (index, reference) in references {
+            let source = dir.join(&reference);
+            let name = match source.file_name() {
+                None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid file name {} in {}", reference, inputname))),
+                Some(name) => name.to_string_lossy().to_string(),
+            };
+            if !source.is_file() {
+                if index > 0 && name == reference {continue}
+                if !defer {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("File {} referenced by {} not found", reference, inputname)))
+                }
+            }
+            if name != reference {renames.insert(reference.clone(), name.clone());}
+            if source.is_file() {
+                add_file(&mut files, &deferred, source, name, &inputname)?;
+            } else if !deferred.contains(&(source.clone(), name.clone())) {
+                if name == inputname || files.iter().chain(deferred.iter()).any(|(_, other)| other == &name) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("File {} referenced by {} has the same name as another file", reference, inputname)))
+                }
+                deferred.push((source, name));
+            }
+        }
+
+        // The outputs of %base are written to the job folder as well
+        for base in self.steps.iter().filter_map(|s| s.block("base")).filter_map(|b| b.value.as_ref()) {
+            if let Some(name) = Path::new(base).file_name().map(|n| n.to_string_lossy().to_string()) {
+                if &name != base {renames.insert(base.clone(), name);}
+            }
+        }
+
+        let autostart = !self.steps.iter().flat_map(|s| s.keywords.iter()).any(|k| k.eq_ignore_ascii_case("noautostart"));
+        // A referenced file of the same name takes its place
+        let gbw = inputfile.with_extension("gbw");
+        let gbwname = gbw.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if autostart && gbw.is_file() && !files.iter().chain(deferred.iter()).any(|(_, name)| name == &gbwname) {
+            add_file(&mut files, &deferred, gbw, gbwname, &inputname)?;
+        }
+
+        Ok(Bundle { input: rewrite_files(&content, &renames), files, deferred })
+    }
+}
+
+// Adds a file to the bundle, unless the same file is already in it. Two
+// different files with the same name cannot both be copied in the job folder.
+fn add_file(files: &mut Vec<(PathBuf, String)>, deferred: &[(PathBuf, String)], source: PathBuf, name: String, inputname: &str) -> io::Result<()> {
+    if deferred.iter().any(|(_, other)| other == &name) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("File {} referenced by {} has the same name as another file", source.display(), inputname)))
+    }
+    let canonical = fs::canonicalize(&source)?;
+    for (other, othername) in files.iter() {
+        if othername != &name {continue}
+        if fs::canonicalize(other)? == canonical {return Ok(())}
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Files {} and {} referenced by {} have the same name", other.display(), source.display(), inputname)))
+    }
+    if name == inputname {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("File {} has the same name as the input", source.display())))
+    }
+    files.push((source, name));
+    Ok(())
+}
+
+// Quoted values of the blocks, which may name files read by orca under keys
+// missing from FILE_KEYS. The name of %base is an output, not a file to read.
+fn quoted_values(content: &str) -> Vec<String> {
+    let mut values = vec![];
+    for line in content.lines() {
+        let tokens = tokenize_spans(line);
+        let first = tokens.first().map(|t| t.text.to_lowercase()).unwrap_or_default();
+        if first.starts_with('!') || first == "%base" {continue}
+        values.extend(tokens.into_iter().filter(|t| t.quoted && !t.text.is_empty()).map(|t| t.text));
+    }
+    values
+}
+
+// Replaces the references to files in the input, keeping the rest of each
+// line as written. The first token of a line is never a file name.
+fn rewrite_files(content: &str, renames: &HashMap<String, String>) -> String {
+    if renames.is_empty() {return content.to_string()}
+    let mut rewritten = String::new();
+    for line in content.split_inclusive('\n') {
+        let mut line = line.to_string();
+        for token in tokenize_spans(&line).into_iter().skip(1).rev() {
+            if let Some(name) = renames.get(&token.text) {
+                let replacement = if token.quoted || name.contains(char::is_whitespace) {format!("\"{}\"", name)} else {name.clone()};
+                line.replace_range(token.span, &replacement);
+            }
+        }
+        rewritten.push_str(&line);
+    }
+    rewritten
+}
+
+// Lines that can only start outside of a block
+fn is_toplevel(tokens: &[String]) -> bool {
+    tokens.first().is_some_and(|t| t.starts_with(['%', '!', '*']) || t.eq_ignore_ascii_case("$new_job"))
+}
+
+// Parses the block opened at the line before start, whose remaining tokens
+// are words, until its end. Returns the block and the index of the line that
+// follows it. Sub-blocks that are not in SUB_BLOCKS, such as the ones of newer
+// orca versions, cannot be told apart from entries, so the block is closed by
+// the last end before the next line that starts outside of a block, and the
+// ends before it close its sub-blocks.
+fn parse_block(lines: &[Vec<String>], name: String, words: Vec<String>, start: usize) -> io::Result<(Block, usize)> {
+    let opened = start;
+    let mut block = Block { name, entries: vec![], value: None, line: opened };
+    let stop = lines[start..].iter().position(|l| is_toplevel(l)).map_or(lines.len(), |p| start + p);
+    let mut body = vec![(start, &words)];
+    body.extend((start..stop).map(|index| (index + 1, &lines[index])));
+    let last = body.iter().rposition(|(_, tokens)| tokens.iter().any(|t| t.eq_ignore_ascii_case("end")))
+        .ok_or_else(|| parse_error(opened, format!("block %{} is not closed by end", block.name)))?;
+    let lastend = body[last].1.iter().rposition(|t| t.eq_ignore_ascii_case("end")).unwrap_or(0);
+
+    // Entries are read line by line, an end closes the innermost block
+    let mut depth = 1;
+    for (position, (_, tokens)) in body[..=last].iter().enumerate() {
+        let mut entry: Vec<String> = vec![];
+        for (column, token) in tokens.iter().enumerate() {
+            if position == last && column == lastend {break}
+            let lower = token.to_lowercase();
+            if lower == "end" {
+                // Closes a sub-block that is not in SUB_BLOCKS at depth 1
+                depth = (depth - 1).max(1);
+            } else if column == 0 && SUB_BLOCKS.contains(&lower.as_str()) {
+                depth += 1;
+            } else if depth == 1 {
+                entry.push(token.clone());
+            }
+        }
+        if let Some((key, value)) = entry.split_first() {
+            block.entries.push((key.to_lowercase(), value.join(" ")));
+        }
+    }
+    Ok((block, body[last].0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XYZ: &str = "* xyz 0 1\nH 0 0 0\nH 0 0 0.74\n*\n";
+
+    fn parse(content: &str) -> OrcaInput {
+        OrcaInput::parse(content).unwrap()
+    }
+
+    #[test]
+    fn pal_block() {
+        assert_eq!(parse(&format!("! B3LYP def2-SVP\n%pal\n  nprocs 4\nend\n{}", XYZ)).nprocs(), Some(4));
+    }
+
+    #[test]
+    fn block_named_like_pal() {
+        let input = parse(&format!("%palxyz nprocs 8 end\n{}", XYZ));
+        assert_eq!(input.nprocs(), None);
+        assert!(input.steps[0].block("palxyz").is_some());
+    }
+
+    #[test]
+    fn end_in_comment() {
+        let input = parse(&format!("%pal # until the end\n  nprocs 6 # not the end\nend\n{}", XYZ));
+        assert_eq!(input.nprocs(), Some(6));
+    }
+
+    #[test]
+    fn pal_keyword() {
+        assert_eq!(parse(&format!("! B3LYP PAL8\n{}", XYZ)).nprocs(), Some(8));
+        assert_eq!(parse(&format!("! B3LYP PAL 8\n{}", XYZ)).nprocs(), Some(8));
+        assert_eq!(parse(&format!("! B3LYP pal4 def2-SVP\n{}", XYZ)).nprocs(), Some(4));
+    }
+
+    #[test]
+    fn one_line_pal_block() {
+        let input = parse(&format!("%pal nprocs 8 end\n%maxcore 3000\n{}", XYZ));
+        assert_eq!(input.nprocs(), Some(8));
+        assert_eq!(input.maxcore(), Some(3000));
+        assert_eq!(input.charge_mult(), Some((0, 1)));
+    }
+
+    #[test]
+    fn new_job_steps() {
+        let input = parse(&format!("! Opt PAL2\n{}\n$new_job\n! Freq\n%pal nprocs 4 end\n* xyzfile 1 2 opt.xyz\n", XYZ));
+        assert_eq!(input.steps.len(), 2);
+        assert_eq!(input.steps[0].nprocs(), Some(2));
+        assert_eq!(input.steps[1].nprocs(), Some(4));
+        assert_eq!(input.nprocs(), Some(4));
+        assert_eq!(input.charge_mult(), Some((0, 1)));
+        assert_eq!(input.steps[1].geometry.as_ref().and_then(|g| g.file.as_deref()), Some("opt.xyz"));
+    }
+
+    #[test]
+    fn geometry_without_space() {
+        let geometry = parse("*xyz -1 2\nCl 0 0 0\n*\n").steps[0].geometry.clone().unwrap();
+        assert_eq!((geometry.kind.as_str(), geometry.charge, geometry.mult, geometry.line, geometry.end), ("xyz", -1, 2, 1, 3));
+    }
+
+    #[test]
+    fn nested_rotate() {
+        let input = parse(&format!("%scf Rotate {{48,49,90,1,1}} end end\n%pal nprocs 2 end\n{}", XYZ));
+        assert!(input.steps[0].block("scf").is_some());
+        assert_eq!(input.nprocs(), Some(2));
+    }
+
+    #[test]
+    fn nested_modify_internal() {
+        let input = parse(&format!("%geom Modify_Internal {{B 0 1 A}} end end\n%geom\n  Modify_Internal\n    {{ B 0 1 A }}\n  end\n  MaxIter 50\nend\n{}", XYZ));
+        assert_eq!(input.steps[0].block("geom").and_then(|b| b.get("maxiter")), Some("50"));
+    }
+
+    #[test]
+    fn nested_newblock() {
+        let input = parse(&format!("%mrci NewBlock 1 * nroots 2 refs cas(2,2) end end end\n%pal nprocs 2 end\n{}", XYZ));
+        assert!(input.steps[0].block("mrci").is_some());
+        assert_eq!(input.nprocs(), Some(2));
+    }
+
+    #[test]
+    fn unknown_sub_block() {
+        let input = parse(&format!("%casscf\n  nel 2\n  newsubblock\n    value 1\n  end\n  norb 2\nend\n%pal nprocs 3 end\n{}", XYZ));
+        let casscf = input.steps[0].block("casscf").unwrap();
+        assert_eq!((casscf.get("nel"), casscf.get("norb")), (Some("2"), Some("2")));
+        assert_eq!(input.nprocs(), Some(3));
+    }
+
+    #[test]
+    fn file_keys() {
+        let input = parse(&format!("%neb\n  NEB_End_XYZFile \"product.xyz\"\n  NEB_TS_XYZFile ts.xyz\nend\n%esd\n  GSHessian \"gs.hess\"\n  ESHessian \"es.hess\"\nend\n{}", XYZ));
+        assert_eq!(input.steps[0].referenced_files(), ["product.xyz", "ts.xyz", "gs.hess", "es.hess"]);
+    }
+
+    #[test]
+    fn bundle_quoted_files() {
+        let dir = std::env::temp_dir().join(format!("orcajob-bundle-{}", std::process::id()));
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join("product.xyz"), "").unwrap();
+        fs::write(dir.join("data/extra.dat"), "").unwrap();
+        let content = format!("%base \"job\"\n%neb NEB_End_XYZFile \"product.xyz\" end\n%newblock SomeFile \"data/extra.dat\" Label \"none\" end\n{}", XYZ);
+        fs::write(dir.join("job.inp"), &content).unwrap();
+        let bundle = parse(&content).bundle(&dir.join("job.inp"), false);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let bundle = bundle.unwrap();
+        let names = bundle.files.iter().map(|(_, name)| name.as_str()).collect::<Vec<&str>>();
+        assert_eq!(names, ["product.xyz", "extra.dat"]);
+        assert!(bundle.input.contains("SomeFile \"extra.dat\""));
+    }
+
+    #[test]
+    fn unclosed_block() {
+        assert!(OrcaInput::parse(&format!("%pal nprocs 4\n{}", XYZ)).is_err());
+        assert!(OrcaInput::parse("* xyz 0 1\nH 0 0 0\n").is_err());
+    }
+}
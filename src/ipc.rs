@@ -0,0 +1,126 @@
+use crate::common::{socket_file, user_name};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+// The CLI talks to the daemon over a unix socket in the root. Each request is
+// a json object on its own line, with the operation in "op":
+//   {"op": "submit", "id": <id>}          commits a job staged in .<id>
+//   {"op": "stop", "id": <id>}
+//   {"op": "hold", "id": <id>}
+//   {"op": "release", "id": <id>}
+//   {"op": "requeue", "id": <id>}
+//   {"op": "status"}
+// and each answer is a json object on its own line, {"ok": true, ...} with
+// the result or {"ok": false, "error": <message>}.
+// The socket is open to every user, so the listener sets the "user" of each
+// request to the owner of the client process, as given by the kernel.
+
+// Time a client waits for an answer, the daemon may be busy copying results
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// A request received by the listener, with the channel to send the answer to
+pub type Request = (Value, Sender<Value>);
+
+pub fn reply_ok(message: &str) -> Value {
+    json!({"ok": true, "message": message})
+}
+
+pub fn reply_error(error: &io::Error) -> Value {
+    json!({"ok": false, "error": error.to_string()})
+}
+
+// Sends a request to the daemon and returns its answer. Returns None if no
+// daemon is listening, in which case the caller falls back to the job files.
+pub fn send(request: Value) -> Option<io::Result<Value>> {
+    let stream = UnixStream::connect(socket_file()).ok()?;
+    Some(exchange(stream, request))
+}
+
+fn exchange(mut stream: UnixStream, request: Value) -> io::Result<Value> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response = serde_json::from_str::<Value>(&line)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid answer from the daemon: {}", e)))?;
+    if response.get("ok").and_then(|v| v.as_bool()) != Some(true) {
+        let error = response.get("error").and_then(|v| v.as_str()).unwrap_or("Request refused by the daemon");
+        return Err(io::Error::other(error.to_string()))
+    }
+    Ok(response)
+}
+
+// Binds the socket and forwards the requests of every client to the returned
+// channel, from which the daemon answers them in its main loop. Fails if
+// another daemon is already listening.
+pub fn listen() -> io::Result<Receiver<Request>> {
+    let path = socket_file();
+    if UnixStream::connect(&path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("Another daemon is listening on {}", path.display())))
+    }
+    // Left behind by a daemon that did not exit cleanly
+    if path.exists() {fs::remove_file(&path)?;}
+    let listener = UnixListener::bind(&path)?;
+    // Every user submits through the same daemon
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o666))?;
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            thread::spawn(move || serve(stream, sender));
+        }
+    });
+    Ok(receiver)
+}
+
+// Uid of the process at the other end of the socket
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut credentials = libc::ucred {pid: 0, uid: 0, gid: 0};
+    let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let code = unsafe {
+        libc::getsockopt(stream.as_raw_fd(), libc::SOL_SOCKET, libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void, &mut length)
+    };
+    if code != 0 {return Err(io::Error::last_os_error())}
+    Ok(credentials.uid)
+}
+
+// Answers the requests of a single client until it disconnects
+fn serve(stream: UnixStream, requests: Sender<Request>) {
+    let mut writer = match stream.try_clone() {
+        Err(_) => return,
+        Ok(writer) => writer,
+    };
+    let user = peer_uid(&stream).ok().and_then(user_name);
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Err(_) => return,
+            Ok(line) => line,
+        };
+        if line.trim().is_empty() {continue}
+        let response = match (serde_json::from_str::<Value>(&line), &user) {
+            (Err(e), _) => reply_error(&io::Error::new(io::ErrorKind::InvalidData, format!("Invalid request: {}", e))),
+            (Ok(_), None) => reply_error(&io::Error::new(io::ErrorKind::PermissionDenied, "Cannot identify the user of the client")),
+            (Ok(mut request), Some(user)) => {
+                if let Some(fields) = request.as_object_mut() {fields.insert("user".to_string(), json!(user));}
+                let (sender, receiver) = mpsc::channel();
+                if requests.send((request, sender)).is_err() {return}
+                match receiver.recv() {
+                    Err(_) => return,
+                    Ok(response) => response,
+                }
+            },
+        };
+        if writeln!(writer, "{}", response).is_err() {return}
+    }
+}
@@ -1,34 +1,46 @@
+pub mod analysis;
 pub mod common;
+pub mod copyback;
+pub mod cleanup;
+pub mod notify;
+pub mod ipc;
+pub mod input;
+pub mod store;
 
 
 extern crate toml;
 extern crate clap;
-use whoami;
 
 use clap::{arg, ArgAction, Command};
 
-use common::{JOBS_FILE,CONF_FILE, merge_toml, JOBS_FOLD, JOBS_LOCK, release_lock, acquire_lock_wait, WORK_FILE, DONE_FILE};
+use common::{conf_file, merge_toml, jobs_fold, set_root, release_lock, acquire_lock_wait, findfile, timestamp, read_orcarc, parse_duration};
+use common::{stop_file, stop_lock, read_jobfile, write_jobfile, set_jobvalue, append_id, is_admin, check_owner};
+use store::{JobRecord, State, read_jobs, usedcores, usedmemory};
+use serde_json::json;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use std::io::{Write, Seek, Read};
+use std::io::{Read, Seek, Write};
 use std::{io, path};
 use std::fs;
-use std::time::{SystemTime,UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
 use prettytable::{row, Table};
 
-// fn main(){
-//     println!("{:?}", readjob(&"141nb1giytlos64t".to_string()).unwrap());
-// }
 fn main(){
     // Initialize command parser
     let mut command = Command::new("orcajob").version("0.1.0")
+        .arg(arg!(root: --root <path> "The orcajob environment, defaults to $ORCAJOB_HOME, /var/lib/orcajob or $XDG_DATA_HOME/orcajob").global(true))
         .subcommand(Command::new("job").about("Schedules a job for execution")
             .args(&[
-                arg!(path: [path] "The path of the job folder").default_value(".").required(false)
-                ])) 
+                arg!(path: [path] "The path of the job folder, or with --array the folders and inputs to submit").default_value(".").required(false).num_args(1..),
+                arg!(array: --array "Submits every input of the folders and every given input as one array job").action(ArgAction::SetTrue),
+                arg!(after: --after <id> "Starts the job after this job, can be repeated").action(ArgAction::Append),
+                arg!(dependency: --dependency <type> "afterok starts the job if the jobs of --after ended with DONE and cancels it otherwise, afterany once they ended")
+                    .value_parser(["afterok", "afterany"])
+                ]))
         .subcommand(Command::new("stop").about("Stops a scheduled command")
             .args(&[
-                arg!(id: <id> "The job id to stop, returned by orcajob status")
+                arg!(id: <id> "The job id or id prefix to stop, returned by orcajob status")
                 ]))
         .subcommand(Command::new("status").about("Returns the status of the commands")
             .args(&[
@@ -39,16 +51,50 @@ fn main(){
                 arg!(all: -A --all "Lists jobs launched by any user").action(ArgAction::SetTrue),
                 arg!(user: -U --user "Add the user column").action(ArgAction::SetTrue),
                 arg!(id: [id] "The job id")
-                ]));
+                ]))
+        .subcommand(Command::new("gc").about("Deletes the folders of jobs that ended more than deleteafter ago")
+            .args(&[
+                arg!(dryrun: -n --"dry-run" "Lists the jobs that would be deleted without deleting them").action(ArgAction::SetTrue)
+                ]))
+        .subcommand(Command::new("pin").about("Keeps a job folder from being deleted by gc")
+            .args(&[
+                arg!(unpin: -u --unpin "Allows the job folder to be deleted again").action(ArgAction::SetTrue),
+                arg!(id: <id> "The job id or id prefix to pin")
+                ]))
+        .subcommand(Command::new("hold").about("Keeps a queued job from being started")
+            .args(&[
+                arg!(id: <id> "The job id or id prefix to hold")
+                ]))
+        .subcommand(Command::new("release").about("Puts a held job back in the queue")
+            .args(&[
+                arg!(id: <id> "The job id or id prefix to release")
+                ]))
+        .subcommand(Command::new("requeue").about("Puts a finished, failed or cancelled job back in the queue")
+            .args(&[
+                arg!(id: <id> "The job id or id prefix to requeue")
+                ]))
+        .subcommand(Command::new("update").about("Changes the scheduling of a job that did not start")
+            .args(&[
+                arg!(priority: --priority <priority> "The new priority").value_parser(clap::value_parser!(i64)),
+                arg!(maxtime: --maxtime <duration> "The new maxtime, e.g. 30m or 4h"),
+                arg!(after: --after <id> "Replaces the dependencies of the job, can be repeated. An empty id removes them").action(ArgAction::Append),
+                arg!(dependency: --dependency <type> "The new dependency type").value_parser(["afterok", "afterany"]),
+                arg!(nprocs: --nprocs <nprocs> "Refused, nprocs must match the PAL of the input").value_parser(clap::value_parser!(i64)),
+                arg!(id: <id> "The job id or id prefix to update")
+                ]))
+        .subcommand(Command::new("watch").about("Follows the progress of a job until it ends")
+            .args(&[
+                arg!(output: -o --output "Prints the output of the job as it grows instead of its progress").action(ArgAction::SetTrue),
+                arg!(id: <id> "The job id or id prefix to watch")
+                ]))
+        .subcommand(Command::new("migrate").about("Imports the jobs.txt, work.txt and done.txt lists of older versions into the job store. Stop the daemon first"));
 
     // Parse args
     // let matches = command.try_get_matches_from_mut(["orcajob","status"]).unwrap();
     let matches = command.get_matches_mut();
+    set_root(matches.get_one::<String>("root").map(|r| r.as_str()));
     
-    match matcher(matches) {
-        Err(_) => {command.print_help().unwrap();}
-        Ok(_) => (),
-    }
+    if matcher(matches).is_err() {command.print_help().unwrap();}
 }
 
 fn matcher(matches: clap::ArgMatches) -> Result<(), clap::Error> {
@@ -60,9 +106,16 @@ fn matcher(matches: clap::ArgMatches) -> Result<(), clap::Error> {
                 Some(submatches) => {
                     match subcommand {
                         "job" => {
-                            if let Some(path) = submatches.get_one::<String>("path") {
-                                let path = path::PathBuf::from(path);
-                                match schedule_job(&path) {
+                            if let Some(paths) = submatches.get_many::<String>("path") {
+                                let paths = paths.map(path::PathBuf::from).collect::<Vec<path::PathBuf>>();
+                                let after = submatches.get_many::<String>("after").unwrap_or_default().cloned().collect::<Vec<String>>();
+                                let dependency = submatches.get_one::<String>("dependency").map(|d| d.as_str());
+                                let result = match (submatches.get_flag("array"), paths.as_slice()) {
+                                    (true, _) => schedule_array(&paths, &after, dependency),
+                                    (false, [path]) => schedule_job(path, &after, dependency),
+                                    (false, _) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Several paths are submitted with --array")),
+                                };
+                                match result {
                                     Ok(resp) => {println!("{}", resp); Ok(())},
                                     Err(err) => {eprintln!("{}", err); Ok(())}
                                 }
@@ -70,18 +123,82 @@ fn matcher(matches: clap::ArgMatches) -> Result<(), clap::Error> {
                         },
                         "stop" => {
                             if let Some(id) = submatches.get_one::<String>("id") {
-                                match stop_job(id) {
+                                let states = [State::Queued, State::Waiting, State::Held, State::Running];
+                                match for_each_job(id, &states, "stop", stop_job) {
                                     Ok(resp) => {println!("{}", resp); Ok(())},
                                     Err(err) => {eprintln!("{}", err); Ok(())}
                                 }
                             } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
                         },
+                        "gc" => {
+                            let dryrun = submatches.get_flag("dryrun");
+                            match gc(dryrun) {
+                                Ok(resp) => {println!("{}", resp); Ok(())},
+                                Err(err) => {eprintln!("{}", err); Ok(())}
+                            }
+                        },
+                        "pin" => {
+                            if let Some(id) = submatches.get_one::<String>("id") {
+                                let pin = !submatches.get_flag("unpin");
+                                let states = [State::Queued, State::Waiting, State::Held, State::Running, State::Done];
+                                match for_each_job(id, &states, "pin", |job| pin_job(job, pin)) {
+                                    Ok(resp) => {println!("{}", resp); Ok(())},
+                                    Err(err) => {eprintln!("{}", err); Ok(())}
+                                }
+                            } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
+                        },
+                        "hold" | "release" => {
+                            if let Some(id) = submatches.get_one::<String>("id") {
+                                let hold = subcommand == "hold";
+                                let states = if hold {vec![State::Queued, State::Waiting]} else {vec![State::Held]};
+                                match for_each_job(id, &states, subcommand, |job| hold_job(job, hold)) {
+                                    Ok(resp) => {println!("{}", resp); Ok(())},
+                                    Err(err) => {eprintln!("{}", err); Ok(())}
+                                }
+                            } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
+                        },
+                        "requeue" => {
+                            if let Some(id) = submatches.get_one::<String>("id") {
+                                match for_each_job(id, &[State::Done], "requeue", requeue_job) {
+                                    Ok(resp) => {println!("{}", resp); Ok(())},
+                                    Err(err) => {eprintln!("{}", err); Ok(())}
+                                }
+                            } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
+                        },
+                        "update" => {
+                            if let Some(id) = submatches.get_one::<String>("id") {
+                                let update = JobUpdate {
+                                    priority: submatches.get_one::<i64>("priority").copied(),
+                                    maxtime: submatches.get_one::<String>("maxtime").cloned(),
+                                    after: submatches.get_many::<String>("after").map(|a| a.cloned().collect()),
+                                    dependency: submatches.get_one::<String>("dependency").cloned(),
+                                    nprocs: submatches.get_one::<i64>("nprocs").copied(),
+                                };
+                                match update_jobs(id, &update) {
+                                    Ok(resp) => {println!("{}", resp); Ok(())},
+                                    Err(err) => {eprintln!("{}", err); Ok(())}
+                                }
+                            } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
+                        },
+                        "watch" => {
+                            if let Some(id) = submatches.get_one::<String>("id") {
+                                match watch_job(id, submatches.get_flag("output")) {
+                                    Ok(resp) => {println!("{}", resp); Ok(())},
+                                    Err(err) => {eprintln!("{}", err); Ok(())}
+                                }
+                            } else { Err(clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument)) }
+                        },
+                        "migrate" => {
+                            match store::migrate() {
+                                Ok(imported) => {println!("{} jobs imported", imported); Ok(())},
+                                Err(err) => {eprintln!("{}", err); Ok(())}
+                            }
+                        },
                         "status" => {
                             let old = submatches.get_flag("old");
                             let running = submatches.get_flag("running");
                             let completed = submatches.get_flag("completed");
                             let active = submatches.get_flag("active");
-                            let all = submatches.get_flag("all");
                             let user = submatches.get_flag("user");
                             let id = submatches.get_one::<String>("id");
                             // TODO: bundle all settings into a single struct
@@ -91,15 +208,15 @@ fn matcher(matches: clap::ArgMatches) -> Result<(), clap::Error> {
                             // orca status -o     -> orca status -oc
                             // orca status -a     -> orca status -ra
                             // The output flags are not modified (UA...)
-                            let (old,running,completed,active) = match (old,running,completed,active) {
-                                (false,false,false,false) => (false,true,true,true),
-                                (true,false,false,false) => (true,false,true,false),
-                                (false,false,false,true) => (false,true,false,true),
-                                _ => (old,running,completed,active)
+                            let (running,completed,active) = match (old,running,completed,active) {
+                                (false,false,false,false) => (true,true,true),
+                                (true,false,false,false) => (false,true,false),
+                                (false,false,false,true) => (true,false,true),
+                                _ => (running,completed,active)
                             };
 
 
-                            match get_status(&old, &running, &completed, &active, &all, &user, id) {
+                            match get_status(running, completed, active, user, id) {
                                 Ok(resp) => {println!("{}", resp); Ok(())},
                                 Err(err) => {eprintln!("{}", err); Ok(())}
                                 }
@@ -121,75 +238,22 @@ fn generate_random_id() -> String {
             .collect::<String>()
 }
 
-fn findfile(path: &path::PathBuf, ext:&String) -> Option<path::PathBuf> {
-    match fs::read_dir(&path) {
-        Err(_) => None,
-        Ok(entries) => {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let fname = entry.file_name();
-                    if let Some(fname) = fname.to_str() {
-                        if fname.ends_with(ext) {
-                            return Some(path.join(path::PathBuf::from(fname)));
-                        }
-                    }
-                }
-            } None
-        }
-    }
-}
-
-fn parse_nprocs(path: &path::PathBuf) -> io::Result<Option<i64>> {
-    let mut in_pal_block = false;
-    for rawline in fs::read_to_string(path)?.lines() {
-        let line = rawline.to_lowercase();
-        let line = line.trim();
-
-        if line.starts_with("%") && line.contains("pal") {in_pal_block = true; continue;}
-        else if in_pal_block && line.contains("end") {in_pal_block = false; continue;}
-
-        if in_pal_block && line.contains("nprocs") {
-            if let Some(value) = line.split_whitespace().skip(1).next() {
-                if let Ok(value) = value.parse::<i64>() {
-                    return Ok(Some(value))
-                }
-            }
-        }
-
-        if line.starts_with("!") && line.contains("pal") {
-            for block in line.split_whitespace() {
-                if block.contains("pal") {
-                    let value = block
-                            .chars()
-                            .skip_while(|c| !c.is_digit(10))
-                            .take_while(|c| c.is_digit(10))
-                            .collect::<String>();
-                    if let Ok(value) = value.parse::<i64>() {
-                        return Ok(Some(value))
-                    }
-                }
-            }
-        }
-    };
-
-    Ok(None)
-}
-
-fn compile_job(jobtoml: &mut toml::Value, path: &path::PathBuf, jobid: &String, timestamp: u64, orcatoml: toml::Value) -> io::Result<()>{
+// Completes the job toml with the defaults of orcarc and the values read from
+// the input, and returns the files to copy in the job folder. With defer, the
+// files missing from the submission folder are copied when the job starts.
+fn compile_job(jobtoml: &mut toml::Value, path: &path::Path, inputfile: &path::Path, jobid: &String, timestamp: u64, orcatoml: toml::Value, defer: bool) -> io::Result<input::Bundle>{
     let defaultjob = match orcatoml.get("defaultjob") {
         None => {return Err(io::Error::new(io::ErrorKind::InvalidData, "Error in parsing TOML orcarc: missing defaultjob"))}
         Some(defaultjob) => defaultjob.to_owned()
     };
-    let inputfile = match findfile(path, &".inp".to_string()) {
-        None => {return Err(io::Error::new(io::ErrorKind::NotFound, "Inputfile not found"))},
-        Some(inputfile) => inputfile,
-    };
     // Should not panic, the inputfile exists and ends in .inp
     let outputfile = inputfile.to_string_lossy().to_string().strip_suffix(".inp").unwrap().to_string() + ".out";
 
     merge_toml(jobtoml, &defaultjob);
 
-    let nprocs_inp = parse_nprocs(&inputfile)?;
+    // An input without PAL runs serially
+    let orcainput = input::OrcaInput::read(inputfile)?;
+    let nprocs_inp = Some(orcainput.nprocs().unwrap_or(1));
     let nprocs_job = match jobtoml.get("scheduling") {
         None => None,
         Some(toml::Value::Table(schedule)) => {
@@ -200,10 +264,56 @@ fn compile_job(jobtoml: &mut toml::Value, path: &path::PathBuf, jobid: &String,
     match (nprocs_inp, nprocs_job) {
         (Some(n1), Some(n2)) => {
             if n1 != n2 {return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("nprocs collision: {} != {}", n1,n2)))}
+            if let Some(maxproc) = orcatoml.get("maxproc").and_then(|v| v.as_integer()) {
+                if n1 > maxproc {return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("nprocs exceeds maxproc: {} > {}", n1, maxproc)))}
+            }
         },
         _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Set nprocs in both inp and job file"))
     }
 
+    // ORCA uses up to maxcore MB on each process. A memory set in the job file
+    // takes precedence over the estimate.
+    let memory = match jobtoml.get("scheduling").and_then(|s| s.get("memory")) {
+        None => orcainput.maxcore().map(|maxcore| maxcore * nprocs_job.unwrap_or(1)),
+        Some(memory) => match memory.as_integer() {
+            Some(memory) if memory >= 0 => Some(memory),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid memory: {}, use a number of MB", memory))),
+        },
+    };
+    if let Some(memory) = memory {
+        let maxmem = orcatoml.get("maxmem").and_then(|v| v.as_integer()).unwrap_or(0);
+        if maxmem > 0 && memory > maxmem {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("memory exceeds maxmem: {} MB > {} MB", memory, maxmem)))
+        }
+        set_jobvalue(jobtoml, "scheduling", "memory", toml::Value::Integer(memory));
+    }
+
+    if let Some(policy) = jobtoml.get("scheduling").and_then(|s| s.get("restartpolicy")) {
+        if !matches!(policy.as_str(), Some("none") | Some("onfailure") | Some("always")) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid restartpolicy: {}, use none, onfailure or always", policy)))
+        }
+    }
+    if let Some(conflict) = jobtoml.get("after").and_then(|a| a.get("conflict")) {
+        if !conflict.as_str().is_some_and(copyback::valid_conflict) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid conflict: {}, use overwrite, skip or rename", conflict)))
+        }
+    }
+    if let Some(events) = jobtoml.get("notify").and_then(|n| n.get("events")) {
+        let valid = events.as_array().is_some_and(|a| a.iter().all(|e| e.as_str().is_some_and(|e| notify::EVENTS.contains(&e))));
+        if !valid {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid notify events: {}, use {}", events, notify::EVENTS.join(", "))))
+        }
+    }
+    if let Some(maxtime) = jobtoml.get("scheduling").and_then(|s| s.get("maxtime")) {
+        if maxtime.as_str().and_then(parse_duration).is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid maxtime: {}", maxtime)))
+        }
+    }
+    let after = resolve_dependencies(jobtoml)?;
+
+    // The files written by the dependencies are copied when the job starts
+    let bundle = orcainput.bundle(inputfile, defer || !after.is_empty())?;
+
     if let Some(jobtable) = jobtoml.as_table_mut() {
         let mut restable = toml::Table::new();
         restable.insert("path".to_string(), toml::Value::String(path.to_string_lossy().to_string()));
@@ -215,98 +325,617 @@ fn compile_job(jobtoml: &mut toml::Value, path: &path::PathBuf, jobid: &String,
         launchtable.insert("output".to_string(), toml::Value::String(outputfile));
         launchtable.insert("username".to_string(), toml::Value::String(whoami::username()));
 
-
         jobtable.insert("result".to_string(), toml::Value::Table(restable));
         jobtable.insert("launch".to_string(), toml::Value::Table(launchtable));
     }
 
-    Ok(())
+    Ok(bundle)
 }
 
-fn schedule_job(path: &path::PathBuf) -> io::Result<String> {
-    let jobid = generate_random_id();
-    let queuetimestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error").as_secs();
-    let fullpath = match fs::canonicalize(path) {
-        Err(_) => {return Err(io::Error::new(io::ErrorKind::Unsupported, "Cannot resolve path"))},
-        Ok(fullpath) => fullpath
+// Records the files of the bundle in the launch table: launch.bundled lists
+// the files copied with the input, launch.deferred the ones the daemon copies
+// when the job starts. Extra deferred files, such as the outputs of earlier
+// pipeline steps, replace the bundled files of the same name.
+fn set_launch_files(jobtoml: &mut toml::Value, bundle: &mut input::Bundle, extra: Vec<toml::Value>) {
+    let extranames = extra.iter().filter_map(|f| f.get("name").and_then(|v| v.as_str()).map(|n| n.to_string())).collect::<Vec<String>>();
+    bundle.files.retain(|(_, name)| !extranames.contains(name));
+    bundle.deferred.retain(|(_, name)| !extranames.contains(name));
+
+    let mut deferred = bundle.deferred.iter().map(|(source, name)| {
+        let mut file = toml::Table::new();
+        file.insert("source".to_string(), toml::Value::String(source.to_string_lossy().to_string()));
+        file.insert("name".to_string(), toml::Value::String(name.clone()));
+        toml::Value::Table(file)
+    }).collect::<Vec<toml::Value>>();
+    deferred.extend(extra);
+
+    let bundled = bundle.files.iter().map(|(_, name)| name.clone()).chain(extranames)
+        .chain(bundle.deferred.iter().map(|(_, name)| name.clone()))
+        .map(toml::Value::String).collect();
+    set_jobvalue(jobtoml, "launch", "bundled", toml::Value::Array(bundled));
+    if !deferred.is_empty() {
+        set_jobvalue(jobtoml, "launch", "deferred", toml::Value::Array(deferred));
+    }
+}
+
+// Checks the dependencies in scheduling.after and replaces the id prefixes
+// with the full ids. Jobs that already failed are refused with afterok, since
+// the new job would be cancelled right away.
+fn resolve_dependencies(jobtoml: &mut toml::Value) -> io::Result<Vec<String>> {
+    let scheduling = jobtoml.get("scheduling");
+    let dependency = scheduling.and_then(|s| s.get("dependency")).cloned().unwrap_or(toml::Value::String("afterok".to_string()));
+    let afterany = match dependency.as_str() {
+        Some("afterok") => false,
+        Some("afterany") => true,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid dependency: {}, use afterok or afterany", dependency))),
+    };
+    let after = match scheduling.and_then(|s| s.get("after")) {
+        None => return Ok(vec![]),
+        Some(after) => after.as_array().filter(|a| a.iter().all(|i| i.is_str())).cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid after: {}, use a list of job ids", after)))?,
     };
-    let mut jobtoml = match findfile(path, &".job".to_string()) {
+
+    // The id of an array or a pipeline waits for every job of the group
+    let records = read_jobs()?;
+    let mut ids: Vec<String> = vec![];
+    for prefix in after.iter().filter_map(|i| i.as_str()) {
+        let jobs = find_jobs(prefix).map_err(|e| io::Error::new(e.kind(), format!("Dependency {}: {}", prefix, e)))?;
+        for id in jobs {
+            let failed = records.iter().find(|j| j.id == id).filter(|j| j.state == State::Done && j.status != "DONE");
+            if let (false, Some(failed)) = (afterany, failed) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Dependency {} ended with {}", id, failed.status)))
+            }
+            if !ids.contains(&id) {ids.push(id);}
+        }
+    }
+    let value = toml::Value::Array(ids.iter().map(|id| toml::Value::String(id.clone())).collect());
+    set_jobvalue(jobtoml, "scheduling", "after", value);
+    Ok(ids)
+}
+
+// Reads the jobfile of a submission folder. The dependencies given on the
+// command line are added to the ones of the jobfile, and dependency overrides
+// its own.
+fn read_submission(path: &path::Path, after: &[String], dependency: Option<&str>) -> io::Result<(path::PathBuf, toml::Value)> {
+    let jobpath = match findfile(path, ".job") {
         None => {
             eprintln!("The directory is missing a .job file. Create one before proceeding");
             return Err(io::Error::new(io::ErrorKind::NotFound, "Jobfile not found"))
         },
-        Some(jobpath) => {
-            match fs::read_to_string(jobpath) {
-                Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, "Cannot read jobfile")),
-                Ok(cont) => {
-                    match cont.parse::<toml::Value>() {
-                        Err(e) => {eprintln!("{:?}", e);return Err(io::Error::new(io::ErrorKind::InvalidData, "Error in parsing TOML jobfile"))},
-                        Ok(config) => config
-                    }
-                }
+        Some(jobpath) => jobpath,
+    };
+    let mut jobtoml = match fs::read_to_string(&jobpath) {
+        Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, "Cannot read jobfile")),
+        Ok(cont) => {
+            match cont.parse::<toml::Value>() {
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Error in parsing TOML jobfile: {}", e))),
+                Ok(config) => config
             }
         }
     };
-    let orcatoml = {
-        let orcarcpath = path::PathBuf::from(CONF_FILE);
-        match fs::read_to_string(orcarcpath) {
-            Err(_) => return Err(io::Error::new(io::ErrorKind::NotFound, "Cannot read orcarc")),
-            Ok(cont) => {
-                match cont.parse::<toml::Value>() {
-                    Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "Error in parsing TOML orcarc")),
-                    Ok(config) => config
-                }
+    if !after.is_empty() {
+        let mut ids = jobtoml.get("scheduling").and_then(|s| s.get("after")).and_then(|v| v.as_array()).cloned().unwrap_or_default();
+        ids.extend(after.iter().map(|id| toml::Value::String(id.clone())));
+        set_jobvalue(&mut jobtoml, "scheduling", "after", toml::Value::Array(ids));
+    }
+    if let Some(dependency) = dependency {
+        set_jobvalue(&mut jobtoml, "scheduling", "dependency", toml::Value::String(dependency.to_string()));
+    }
+    Ok((jobpath, jobtoml))
+}
+
+fn read_orcatoml() -> io::Result<toml::Value> {
+    match fs::read_to_string(conf_file()) {
+        Err(_) => Err(io::Error::new(io::ErrorKind::NotFound, "Cannot read orcarc")),
+        Ok(cont) => {
+            match cont.parse::<toml::Value>() {
+                Err(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Error in parsing TOML orcarc")),
+                Ok(config) => Ok(config)
             }
         }
+    }
+}
+
+// Lists the inputs of a folder in order of name
+fn list_inputs(path: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    let mut inputs = fs::read_dir(path)?.flatten().map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "inp"))
+        .collect::<Vec<path::PathBuf>>();
+    inputs.sort();
+    Ok(inputs)
+}
+
+fn schedule_job(path: &path::PathBuf, after: &[String], dependency: Option<&str>) -> io::Result<String> {
+    let jobid = generate_random_id();
+    let queuetimestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error").as_secs();
+    let fullpath = match fs::canonicalize(path) {
+        Err(_) => {return Err(io::Error::new(io::ErrorKind::Unsupported, "Cannot resolve path"))},
+        Ok(fullpath) => fullpath
     };
+    let (jobpath, mut jobtoml) = read_submission(&fullpath, after, dependency)?;
+    let orcatoml = read_orcatoml()?;
 
-    compile_job(&mut jobtoml, &fullpath, &jobid, queuetimestamp, orcatoml)?;
+    let jobfilename = jobpath.file_name().unwrap_or_default();
+    if jobtoml.get("steps").is_some() {
+        return schedule_pipeline(&jobtoml, &fullpath, jobfilename, queuetimestamp, &orcatoml)
+    }
 
-    let jobfolder = path::PathBuf::from(JOBS_FOLD).join(&jobid);
-    fs::create_dir_all(&jobfolder)?;
-    
-    for entry in fs::read_dir(path)?{
-        if let Ok(entry) = entry {
-            let entrypath = entry.path();
-            let destination = jobfolder.join(entry.file_name().to_string_lossy().to_string());
-            if entry.file_name().to_string_lossy().to_string().ends_with(".job") {
-                let mut jfile = fs::File::create(destination)?;
-                let tomlstr = toml::to_string_pretty(&jobtoml).unwrap();
-                let tomlcontent = tomlstr.as_bytes();
-                jfile.write_all(tomlcontent)?;
-            } else {
-                if entrypath.is_file() {
-                    fs::copy(entrypath, destination)?;
+    // Folders with several inputs are submitted with --array
+    let inputfile = match list_inputs(&fullpath)?.as_slice() {
+        [] => {return Err(io::Error::new(io::ErrorKind::NotFound, "Inputfile not found"))},
+        [inputfile] => inputfile.clone(),
+        inputs => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("Found {} inputs in {}, submit them with --array", inputs.len(), fullpath.display()))),
+    };
+    let mut bundle = compile_job(&mut jobtoml, &fullpath, &inputfile, &jobid, queuetimestamp, orcatoml, false)?;
+    set_launch_files(&mut jobtoml, &mut bundle, vec![]);
+    stage_job(&jobid, &jobtoml, &bundle, jobfilename)?;
+    submit_job(&jobid)?;
+    Ok(jobid)
+}
+
+// Submits every input as an element of an array job, <id>_<n>. Paths are
+// either inputs or folders, whose inputs are all submitted, and each input
+// uses the jobfile of its folder. Returns the id of the array.
+fn schedule_array(paths: &[path::PathBuf], after: &[String], dependency: Option<&str>) -> io::Result<String> {
+    let parent = generate_random_id();
+    let queuetimestamp = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time error").as_secs();
+    let orcatoml = read_orcatoml()?;
+
+    let mut inputs = vec![];
+    for path in paths {
+        let fullpath = fs::canonicalize(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("Cannot resolve path {}: {}", path.display(), e)))?;
+        if fullpath.is_dir() {
+            inputs.extend(list_inputs(&fullpath)?);
+        } else if fullpath.extension().is_some_and(|e| e == "inp") {
+            inputs.push(fullpath);
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is neither a folder nor an input", path.display())))
+        }
+    }
+    let mut unique: Vec<path::PathBuf> = vec![];
+    for input in inputs {
+        if !unique.contains(&input) {unique.push(input);}
+    }
+    let inputs = unique;
+    if inputs.is_empty() {return Err(io::Error::new(io::ErrorKind::NotFound, "Inputfile not found"))}
+
+    let mut jobs = vec![];
+    for (index, inputfile) in inputs.iter().enumerate() {
+        let id = format!("{}_{}", parent, index + 1);
+        let dir = inputfile.parent().unwrap_or(path::Path::new("/"));
+        let inputname = inputfile.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let (jobpath, mut jobtoml) = read_submission(dir, after, dependency)?;
+        if jobtoml.get("steps").is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The jobfile {} defines steps, which arrays do not support", jobpath.display())))
+        }
+        set_jobvalue(&mut jobtoml, "array", "parent", toml::Value::String(parent.clone()));
+        set_jobvalue(&mut jobtoml, "array", "index", toml::Value::Integer(index as i64 + 1));
+        set_jobvalue(&mut jobtoml, "array", "size", toml::Value::Integer(inputs.len() as i64));
+        set_jobvalue(&mut jobtoml, "array", "input", toml::Value::String(inputname.clone()));
+
+        let mut bundle = compile_job(&mut jobtoml, dir, inputfile, &id, queuetimestamp, orcatoml.clone(), false)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", inputfile.display(), e)))?;
+        set_launch_files(&mut jobtoml, &mut bundle, vec![]);
+        let jobfilename = jobpath.file_name().unwrap_or_default().to_os_string();
+        jobs.push(CompiledJob { id, jobtoml, bundle, jobfilename });
+    }
+    submit_group(&jobs)?;
+    Ok(parent)
+}
+
+// Copies a compiled job to its folder. The folder is copied under a hidden
+// name and only renamed once the job is added to the store, so a crash never
+// leaves a job without its folder. Only the input, the jobfile and the files
+// read by the input are copied.
+fn stage_job(jobid: &str, jobtoml: &toml::Value, bundle: &input::Bundle, jobfilename: &std::ffi::OsStr) -> io::Result<()> {
+    let staging = jobs_fold().join(format!(".{}", jobid));
+    fs::create_dir_all(&staging)?;
+
+    let inputname = jobtoml.get("launch").and_then(|l| l.get("input")).and_then(|v| v.as_str())
+        .and_then(|i| path::Path::new(i).file_name()).map(|n| n.to_os_string()).unwrap_or_default();
+    fs::write(staging.join(inputname), &bundle.input)?;
+    for (source, name) in bundle.files.iter() {
+        fs::copy(source, staging.join(name))?;
+    }
+    let mut jfile = fs::File::create(staging.join(jobfilename))?;
+    let tomlstr = toml::to_string_pretty(jobtoml).unwrap();
+    jfile.write_all(tomlstr.as_bytes())?;
+    Ok(())
+}
+
+// Adds a staged job to the store. The daemon starts the job right away,
+// without the daemon it is picked from the store later.
+fn submit_job(jobid: &str) -> io::Result<()> {
+    match ipc::send(json!({"op": "submit", "id": jobid})) {
+        Some(result) => {result?;},
+        None => {store::commit_job(jobid)?;},
+    }
+    Ok(())
+}
+
+// A job compiled with the other elements of an array or steps of a pipeline
+struct CompiledJob {
+    id: String,
+    jobtoml: toml::Value,
+    bundle: input::Bundle,
+    jobfilename: std::ffi::OsString,
+}
+
+// Stages and submits the jobs of an array or pipeline. Nothing is submitted
+// until every job is staged, and the jobs submitted before a failure are
+// cancelled.
+fn submit_group(jobs: &[CompiledJob]) -> io::Result<()> {
+    let unstage = |jobs: &[CompiledJob]| for job in jobs {let _ = fs::remove_dir_all(jobs_fold().join(format!(".{}", job.id)));};
+    for job in jobs.iter() {
+        if let Err(e) = stage_job(&job.id, &job.jobtoml, &job.bundle, &job.jobfilename) {
+            unstage(jobs);
+            return Err(e)
+        }
+    }
+    for (index, job) in jobs.iter().enumerate() {
+        if let Err(e) = submit_job(&job.id) {
+            for job in jobs[..index].iter() {let _ = stop_job(&job.id);}
+            unstage(&jobs[index..]);
+            return Err(e)
+        }
+    }
+    Ok(())
+}
+
+// Keys of a [[steps]] table that describe the step itself, the others are
+// tables of the jobfile overridden for the step
+const STEP_KEYS: [&str; 3] = ["name", "input", "use"];
+
+// Expands the [[steps]] of a jobfile into a job per step, <id>.<n>, each one
+// waiting for the previous one to end with DONE. A step may use files written
+// by earlier steps through [[steps.use]] tables:
+//   step = "opt"             the name of the earlier step
+//   file = "opt_trj.xyz"     the file in its job folder
+//   as = "start.xyz"         the name in the job folder of this step, defaults to file
+//   frame = "last"           only copies the last geometry of a trajectory
+// Returns the id of the pipeline.
+fn schedule_pipeline(jobtoml: &toml::Value, path: &path::Path, jobfilename: &std::ffi::OsStr, timestamp: u64, orcatoml: &toml::Value) -> io::Result<String> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let steps = jobtoml.get("steps").and_then(|v| v.as_array()).filter(|s| !s.is_empty() && s.iter().all(|s| s.is_table()))
+        .ok_or_else(|| invalid("Invalid steps, use one [[steps]] table per step".to_string()))?;
+    let parent = generate_random_id();
+    let mut base = jobtoml.clone();
+    if let Some(table) = base.as_table_mut() {table.remove("steps");}
+
+    let mut names: Vec<String> = vec![];
+    let mut jobs: Vec<CompiledJob> = vec![];
+    for (index, step) in steps.iter().enumerate() {
+        let number = index + 1;
+        let id = format!("{}.{}", parent, number);
+        let name = match step.get("name") {
+            None => format!("step{}", number),
+            Some(name) => name.as_str().filter(|n| !n.is_empty()).map(|n| n.to_string())
+                .ok_or_else(|| invalid(format!("Invalid name of step {}: {}", number, name)))?,
+        };
+        if names.contains(&name) {return Err(invalid(format!("Duplicate step name {}", name)))}
+        let inputfile = match step.get("input").and_then(|v| v.as_str()) {
+            None => return Err(invalid(format!("Missing input in step {}", name))),
+            Some(input) => path.join(input),
+        };
+        if !inputfile.is_file() || inputfile.extension().is_none_or(|e| e != "inp") {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Input {} of step {} not found", inputfile.display(), name)))
+        }
+
+        // The tables of the step override the ones of the jobfile, the
+        // dependencies of the jobfile only apply to the first step
+        let mut steptoml = base.clone();
+        for (key, value) in step.as_table().into_iter().flatten().filter(|(k, _)| !STEP_KEYS.contains(&k.as_str())) {
+            match (value.as_table(), steptoml.get_mut(key).and_then(|t| t.as_table_mut())) {
+                (Some(values), Some(table)) => table.extend(values.clone()),
+                _ => {steptoml.as_table_mut().map(|t| t.insert(key.clone(), value.clone()));},
+            }
+        }
+        if number > 1 {
+            if let Some(scheduling) = steptoml.get_mut("scheduling").and_then(|s| s.as_table_mut()) {scheduling.remove("after");}
+        }
+        set_jobvalue(&mut steptoml, "pipeline", "parent", toml::Value::String(parent.clone()));
+        set_jobvalue(&mut steptoml, "pipeline", "step", toml::Value::String(name.clone()));
+        set_jobvalue(&mut steptoml, "pipeline", "index", toml::Value::Integer(number as i64));
+        set_jobvalue(&mut steptoml, "pipeline", "steps", toml::Value::Integer(steps.len() as i64));
+
+        let mut bundle = compile_job(&mut steptoml, path, &inputfile, &id, timestamp, orcatoml.clone(), number > 1)?;
+
+        let mut uses = vec![];
+        for used in step.get("use").and_then(|v| v.as_array()).into_iter().flatten() {
+            let field = |key: &str| used.get(key).and_then(|v| v.as_str());
+            let from = field("step").ok_or_else(|| invalid(format!("Missing step in a use of step {}", name)))?;
+            let fromindex = names.iter().position(|n| n == from)
+                .ok_or_else(|| invalid(format!("Step {} uses {}, which is not an earlier step", name, from)))?;
+            let file = field("file").ok_or_else(|| invalid(format!("Missing file in a use of step {}", name)))?;
+            let target = field("as").unwrap_or(file);
+            for filename in [file, target] {
+                if path::Path::new(filename).file_name() != Some(std::ffi::OsStr::new(filename)) {
+                    return Err(invalid(format!("Invalid file name {} in step {}, use a name without folder", filename, name)))
                 }
             }
+            let mut table = toml::Table::new();
+            let source = jobs_fold().join(format!("{}.{}", parent, fromindex + 1)).join(file);
+            table.insert("source".to_string(), toml::Value::String(source.to_string_lossy().to_string()));
+            table.insert("name".to_string(), toml::Value::String(target.to_string()));
+            match field("frame") {
+                None => (),
+                Some("last") => {table.insert("frame".to_string(), toml::Value::String("last".to_string()));},
+                Some(frame) => return Err(invalid(format!("Invalid frame {} in step {}, use last", frame, name))),
+            }
+            uses.push(toml::Value::Table(table));
         }
+        set_launch_files(&mut steptoml, &mut bundle, uses);
+
+        if let Some(previous) = jobs.last() {
+            set_jobvalue(&mut steptoml, "scheduling", "after", toml::Value::Array(vec![toml::Value::String(previous.id.clone())]));
+            set_jobvalue(&mut steptoml, "scheduling", "dependency", toml::Value::String("afterok".to_string()));
+        }
+        names.push(name);
+        jobs.push(CompiledJob { id, jobtoml: steptoml, bundle, jobfilename: jobfilename.to_os_string() });
     }
-    
-    let lock = acquire_lock_wait(JOBS_LOCK)?;
-    
-    match fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(true)
-                .open(JOBS_FILE) {
-        Err(e) => {
-            release_lock(&lock)?;
-            return Err(e)
+
+    submit_group(&jobs)?;
+    Ok(parent)
+}
+
+// Resolves an id prefix to the jobs it designates. The id of an array or a
+// pipeline designates all of its jobs, in order.
+fn find_jobs(prefix: &str) -> io::Result<Vec<String>> {
+    let records = read_jobs()?;
+    let mut parents: Vec<&str> = vec![];
+    for record in records.iter().filter(|r| !r.parent.is_empty()) {
+        if !parents.contains(&record.parent.as_str()) {parents.push(&record.parent);}
+    }
+    // The jobs of a group are designated by the group unless the prefix is
+    // longer than the id of the group
+    let mut matching = parents.iter().filter(|p| p.starts_with(prefix)).map(|p| p.to_string()).collect::<Vec<String>>();
+    matching.extend(records.iter().filter(|r| r.id.starts_with(prefix) && !r.parent.starts_with(prefix)).map(|r| r.id.clone()));
+    if parents.contains(&prefix) || records.iter().any(|r| r.id == prefix) {matching = vec![prefix.to_string()];}
+
+    match matching.len() {
+        0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "No job with specified id")),
+        1 if parents.contains(&matching[0].as_str()) => {
+            let mut jobs = records.iter().filter(|r| r.parent == matching[0]).collect::<Vec<&JobRecord>>();
+            jobs.sort_by_key(|r| group_index(r));
+            Ok(jobs.into_iter().map(|r| r.id.clone()).collect())
         },
-        Ok(mut file) => {
-            match writeln!(file, "{}", jobid) {
-                Ok(()) => {release_lock(&lock)?;}
-                Err(e) => {release_lock(&lock)?; return Err(e)}
-            };
+        1 => Ok(matching),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Ambiguous id, matches: {}", matching.join(", ")))),
+    }
+}
+
+// Applies action to the jobs designated by id, see find_jobs. The jobs of an
+// array or a pipeline that are not in one of the states are skipped.
+fn for_each_job(id: &str, states: &[State], verb: &str, action: impl Fn(&str) -> io::Result<String>) -> io::Result<String> {
+    let jobs = find_jobs(id)?;
+    if let [job] = jobs.as_slice() {return action(job)}
+
+    // Running jobs go last, so that the daemon does not start a pending job
+    // of the group while the running ones are stopped
+    let records = read_jobs()?;
+    let mut selected = records.iter().filter(|r| jobs.contains(&r.id) && states.contains(&r.state)).collect::<Vec<&JobRecord>>();
+    selected.sort_by_key(|r| (r.state == State::Running, group_index(r)));
+    if selected.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No job of {} to {}", id, verb)))
+    }
+    let messages = selected.into_iter().map(|job| match action(&job.id) {
+        Ok(message) => message,
+        Err(e) => format!("Job {}: {}", job.id, e),
+    }).collect::<Vec<String>>();
+    Ok(messages.join("\n"))
+}
+
+// Resolves a job id prefix, as accepted by orcajob status, to a single job
+fn find_job(prefix: &str) -> io::Result<String> {
+    let ids = read_jobs()?.into_iter().map(|j| j.id).collect::<Vec<String>>();
+    if ids.iter().any(|id| id == prefix) {return Ok(prefix.to_string())}
+    let mut matching = ids.into_iter().filter(|id| id.starts_with(prefix)).collect::<Vec<String>>();
+    match matching.len() {
+        0 => Err(io::Error::new(io::ErrorKind::InvalidInput, "No job with specified id")),
+        1 => Ok(matching.remove(0)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Ambiguous id, matches: {}", matching.join(", ")))),
+    }
+}
+
+// Collects the jobs older than deleteafter. Admins collect the jobs of every
+// user, the others only their own.
+fn gc(dryrun: bool) -> io::Result<String> {
+    let deleteafter = read_orcarc(&conf_file()).get("deleteafter").and_then(|v| v.as_str()).and_then(parse_duration);
+    let deleteafter = match deleteafter {
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "deleteafter is not set in orcarc, nothing to collect")),
+        Some(deleteafter) => deleteafter,
+    };
+    let currentuser = whoami::username();
+    let user = if is_admin(&currentuser) {None} else {Some(currentuser.as_str())};
+    let collected = cleanup::collect_garbage(deleteafter, user, dryrun)?;
+
+    let mut table = Table::new();
+    table.add_row(row!["ID", "USER", "AGE"]);
+    for job in collected.iter() {
+        table.add_row(row![job.id, job.user, job.age]);
+    }
+    let mut format = prettytable::format::TableFormat::new();
+    format.padding(0, 3);
+    table.set_format(format);
+    if !collected.is_empty() {table.printstd();}
+
+    if dryrun {Ok(format!("{} jobs would be deleted", collected.len()))}
+    else {Ok(format!("{} jobs deleted", collected.len()))}
+}
+
+fn pin_job(job: &str, pin: bool) -> io::Result<String> {
+    let jobdir = jobs_fold().join(job);
+    let (jobpath, mut jobtoml) = read_jobfile(&jobdir)?;
+    check_owner(&jobtoml, &whoami::username())?;
+
+    set_jobvalue(&mut jobtoml, "after", "pin", toml::Value::Boolean(pin));
+    write_jobfile(&jobpath, &jobtoml)?;
+    if pin {Ok(format!("Job {} pinned", job))}
+    else {Ok(format!("Job {} unpinned", job))}
+}
+
+fn stop_job(job: &str) -> io::Result<String> {
+    let jobdir = jobs_fold().join(job);
+    let (_, jobtoml) = read_jobfile(&jobdir)?;
+    let currentuser = whoami::username();
+    check_owner(&jobtoml, &currentuser)?;
+
+    if let Some(response) = ipc::send(json!({"op": "stop", "id": job})) {
+        return Ok(response?.get("message").and_then(|v| v.as_str()).unwrap_or("").to_string())
+    }
+
+    // Queued jobs are cancelled right away
+    if store::cancel_queued(job, &currentuser)? {
+        return Ok(format!("Job {} cancelled", job))
+    }
+
+    // Running jobs are terminated by the daemon once it runs again
+    if !read_jobs()?.iter().any(|j| j.id == job && j.state == State::Running) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} has already ended", job)))
+    }
+    let stoplock = acquire_lock_wait(&stop_lock())?;
+    let result = append_id(&stop_file(), &format!("{} {}", job, currentuser));
+    release_lock(&stoplock)?;
+    result?;
+    Ok(format!("Stop requested for job {}", job))
+}
+
+// Holds a queued job, or releases a held one
+fn hold_job(job: &str, hold: bool) -> io::Result<String> {
+    let (_, jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    check_owner(&jobtoml, &whoami::username())?;
+
+    let op = if hold {"hold"} else {"release"};
+    match ipc::send(json!({"op": op, "id": job})) {
+        Some(result) => {result?;},
+        None if hold => store::hold_job(job)?,
+        None => store::release_job(job)?,
+    }
+    if hold {Ok(format!("Job {} held", job))}
+    else {Ok(format!("Job {} released", job))}
+}
+
+// Puts a job that ended back in the queue
+fn requeue_job(job: &str) -> io::Result<String> {
+    let (_, jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    check_owner(&jobtoml, &whoami::username())?;
+
+    match ipc::send(json!({"op": "requeue", "id": job})) {
+        Some(result) => {result?;},
+        None => {store::requeue_job(job)?;},
+    }
+    Ok(format!("Job {} requeued", job))
+}
+
+// Values given to orcajob update, None for the ones left unchanged
+struct JobUpdate {
+    priority: Option<i64>,
+    maxtime: Option<String>,
+    after: Option<Vec<String>>,
+    dependency: Option<String>,
+    nprocs: Option<i64>,
+}
+
+// Updates the jobs designated by id that did not start. The dependencies of a
+// pipeline chain its steps, so they are only replaced one job at a time.
+fn update_jobs(id: &str, update: &JobUpdate) -> io::Result<String> {
+    if update.nprocs.is_some() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "nprocs cannot be updated, it must match the PAL of the input. Stop the job and submit it again"))
+    }
+    if update.priority.is_none() && update.maxtime.is_none() && update.after.is_none() && update.dependency.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Nothing to update, use --priority, --maxtime, --after or --dependency"))
+    }
+    if let Some(maxtime) = update.maxtime.as_ref().filter(|m| parse_duration(m).is_none()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid maxtime: {}", maxtime)))
+    }
+    if update.after.is_some() && find_jobs(id)?.len() > 1 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} has several jobs, update the dependencies of one of them", id)))
+    }
+    for_each_job(id, &[State::Queued, State::Waiting, State::Held], "update", |job| update_job(job, update))
+}
+
+fn update_job(job: &str, update: &JobUpdate) -> io::Result<String> {
+    let (_, jobtoml) = read_jobfile(&jobs_fold().join(job))?;
+    check_owner(&jobtoml, &whoami::username())?;
+
+    store::update_pending(job, |jobtoml| {
+        if let Some(priority) = update.priority {
+            set_jobvalue(jobtoml, "scheduling", "priority", toml::Value::Integer(priority));
+        }
+        if let Some(maxtime) = &update.maxtime {
+            set_jobvalue(jobtoml, "scheduling", "maxtime", toml::Value::String(maxtime.clone()));
+        }
+        if let Some(dependency) = &update.dependency {
+            set_jobvalue(jobtoml, "scheduling", "dependency", toml::Value::String(dependency.clone()));
+        }
+        if let Some(after) = &update.after {
+            let after = after.iter().filter(|id| !id.is_empty()).map(|id| toml::Value::String(id.clone())).collect();
+            set_jobvalue(jobtoml, "scheduling", "after", toml::Value::Array(after));
+        }
+        if update.after.is_some() || update.dependency.is_some() {
+            let after = resolve_dependencies(jobtoml)?;
+            check_cycle(job, &after)?;
+        }
+        Ok(())
+    })?;
+    Ok(format!("Job {} updated", job))
+}
+
+// Refuses dependencies that wait, directly or not, for the job itself, since
+// none of the jobs would ever start
+fn check_cycle(job: &str, after: &[String]) -> io::Result<()> {
+    let records = read_jobs()?;
+    let mut pending = after.to_vec();
+    let mut seen: Vec<String> = vec![];
+    while let Some(id) = pending.pop() {
+        if id == job {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} would wait for itself", job)))
         }
+        if seen.contains(&id) {continue}
+        if let Some(record) = records.iter().find(|r| r.id == id) {pending.extend(record.after.iter().cloned());}
+        seen.push(id);
     }
+    Ok(())
+}
 
-    Ok(jobid)
+// Cores and memory in use and available, as shown by orcajob status
+struct Usage {
+    usedcores: usize,
+    maxproc: usize,
+    usedmemory: u64,
+    // 0 if the memory is not limited
+    maxmem: u64,
 }
 
-fn stop_job(id: &String) -> io::Result<String> {
-    Ok(id.to_string())
+// Reads the jobs and the resource usage from the daemon, or from the store and
+// orcarc when the daemon is not running
+fn read_status() -> io::Result<(Vec<JobRecord>, Usage)> {
+    if let Some(response) = ipc::send(json!({"op": "status"})) {
+        let response = response?;
+        let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid status from the daemon: {}", e));
+        let jobs = serde_json::from_value::<Vec<JobRecord>>(response.get("jobs").cloned().unwrap_or_default()).map_err(invalid)?;
+        let number = |key: &str| response.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        let usage = Usage {
+            usedcores: number("usedcores") as usize,
+            maxproc: number("maxproc") as usize,
+            usedmemory: number("usedmemory"),
+            maxmem: number("maxmem"),
+        };
+        return Ok((jobs, usage))
+    }
+    let jobs = read_jobs()?;
+    let orcarc = read_orcarc(&conf_file());
+    let usage = Usage {
+        usedcores: usedcores(&jobs),
+        maxproc: orcarc.get("maxproc").and_then(|v| v.as_integer()).unwrap_or(1) as usize,
+        usedmemory: usedmemory(&jobs),
+        maxmem: orcarc.get("maxmem").and_then(|v| v.as_integer()).unwrap_or(0).max(0) as u64,
+    };
+    Ok((jobs, usage))
 }
+
 #[derive(Debug)]
 struct JobData {
     id: String,
@@ -315,123 +944,344 @@ struct JobData {
     ended: u64,
     status: Status,
     user: String,
+    // Steps of a pipeline that ended with DONE and number of steps
+    progress: Option<(usize, usize)>,
 }
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum Status {
     FAILED,
     QUEUED,
+    WAITING,
+    HELD,
+    REQUEUED,
     ACTIVE,
     DONE,
     ERROR,
+    TIMEOUT,
+    CANCELLED,
 }
-fn readjob(job:&String) -> io::Result<JobData> {
-    let jobdir = path::PathBuf::from(JOBS_FOLD.to_string()).join(job);
-    if let Some(jobpath) = findfile(&jobdir, &".job".to_string()) {
-        if let Ok(content) = fs::read_to_string(jobpath) {
-            if let Ok(config) = content.parse::<toml::Value>() {
-                let resulttable = config.get("result").unwrap();
-                let launchtable = config.get("launch").unwrap();
-
-                let user = launchtable.get("username").and_then(|v| v.as_str()).unwrap_or("notset").to_string();
-
-                let id = resulttable.get("id").and_then(|v| v.as_str()).unwrap_or("0000000000000000").to_string();
-                let scheduled = resulttable.get("scheduled").and_then(|v| v.as_integer()).unwrap_or_default() as u64;
-                let launched = resulttable.get("launched").and_then(|v| v.as_integer()).unwrap_or_default() as u64;
-                let ended = resulttable.get("ended").and_then(|v| v.as_integer()).unwrap_or_default() as u64;
-
-                let status = match (scheduled,launched,ended) {
-                    (0,0,0) => Status::FAILED,
-                    (_,0,0) => Status::QUEUED,
-                    (_,_,0) => Status::ACTIVE,
-                    (_,_,_) => {
-                        match findfile(&jobdir, &".out".to_string()) {
-                            None => Status::ERROR,
-                            Some(outpath) => {
-                                let mut outfile = fs::File::open(outpath)?;
-                                let fsize = outfile.seek(io::SeekFrom::End(0))?;
-                                // Here use the min value because fsize-1024 can wrap around
-                                let pos = std::cmp::min(fsize - 1024, 0);
-                                outfile.seek(io::SeekFrom::Start(pos))?;
-                                let mut buffer = String::new();
-                                outfile.read_to_string(&mut buffer)?;
-
-                                if buffer.contains("****ORCA TERMINATED NORMALLY****") {Status::DONE}
-                                else {Status::ERROR}
-                            }
-                        }
-                    },
-                };
-                return Ok(JobData { id, scheduled, launched, ended, status, user })
-            }
+impl Status {
+    fn name(&self) -> &'static str {
+        match self {
+            Status::FAILED => "FAILED",
+            Status::QUEUED => "QUEUED",
+            Status::WAITING => "WAITING",
+            Status::HELD => "HELD",
+            Status::REQUEUED => "REQUEUED",
+            Status::ACTIVE => "ACTIVE",
+            Status::DONE => "DONE",
+            Status::ERROR => "ERROR",
+            Status::TIMEOUT => "TIMEOUT",
+            Status::CANCELLED => "CANCELLED",
+        }
+    }
+    // Parses the status recorded by the daemon in result.status
+    fn from_name(name: &str) -> Option<Status> {
+        match name {
+            "FAILED" => Some(Status::FAILED),
+            "QUEUED" => Some(Status::QUEUED),
+            "WAITING" => Some(Status::WAITING),
+            "HELD" => Some(Status::HELD),
+            "REQUEUED" => Some(Status::REQUEUED),
+            "ACTIVE" => Some(Status::ACTIVE),
+            "DONE" => Some(Status::DONE),
+            "ERROR" => Some(Status::ERROR),
+            "TIMEOUT" => Some(Status::TIMEOUT),
+            "CANCELLED" => Some(Status::CANCELLED),
+            _ => None,
+        }
+    }
+}
+impl JobData {
+    fn from_record(record: &JobRecord) -> JobData {
+        let status = match record.state {
+            // Requeued jobs are told apart until they start again
+            State::Queued if record.requeues > 0 => Status::REQUEUED,
+            State::Queued => Status::QUEUED,
+            State::Waiting => Status::WAITING,
+            State::Held => Status::HELD,
+            State::Running => Status::ACTIVE,
+            // Jobs that ended without a recorded status never started
+            _ => Status::from_name(&record.status).unwrap_or(Status::FAILED),
+        };
+        JobData {
+            id: record.id.clone(),
+            scheduled: record.scheduled,
+            launched: record.launched,
+            ended: record.ended,
+            status,
+            user: record.user.clone(),
+            progress: None,
+        }
+    }
+
+    // Summarizes the jobs of a pipeline or an array, in order, as a single
+    // job. It is active while one of its jobs runs. A pipeline then ends with
+    // the status of the first step that did not end with DONE, and otherwise
+    // has the status of its next step. An array has the status of its first
+    // pending element until they all ended.
+    fn from_group(parent: &str, steps: &[&JobRecord], array: bool) -> JobData {
+        let ended = |s: &&&JobRecord| matches!(s.state, State::Done | State::Deleted);
+        let done = steps.iter().filter(|s| ended(s) && s.status == "DONE").count();
+        let failed = steps.iter().find(|s| ended(s) && s.status != "DONE");
+        let next = steps.iter().find(|s| !ended(s));
+        let status = if steps.iter().any(|s| s.state == State::Running) {
+            Status::ACTIVE
+        } else if let Some(next) = next.filter(|_| array || failed.is_none()) {
+            JobData::from_record(next).status
+        } else if let Some(failed) = failed {
+            JobData::from_record(failed).status
+        } else {
+            Status::DONE
+        };
+        let finished = !matches!(status, Status::ACTIVE | Status::QUEUED | Status::WAITING | Status::HELD | Status::REQUEUED);
+        JobData {
+            id: parent.to_string(),
+            scheduled: steps.first().map(|s| s.scheduled).unwrap_or(0),
+            launched: steps.iter().map(|s| s.launched).find(|l| *l > 0).unwrap_or(0),
+            ended: if finished {steps.iter().map(|s| s.ended).max().unwrap_or(0)} else {0},
+            status,
+            user: steps.first().map(|s| s.user.clone()).unwrap_or_default(),
+            progress: Some((done, steps.len())),
+        }
+    }
+
+    fn status_name(&self) -> String {
+        match self.progress {
+            None => self.status.name().to_string(),
+            Some((done, total)) => format!("{} {}/{}", self.status.name(), done, total),
+        }
+    }
+
+    fn elapsed(&self) -> String {
+        match (self.launched, self.ended) {
+            // Jobs that failed to launch never ran
+            (0, _) | (_, 0) => "-".to_string(),
+            _ => self.ended.saturating_sub(self.launched).to_string(),
+        }
+    }
+}
+
+// Index of a job in its pipeline or array, from its id <parent>.<n> or <parent>_<n>
+fn group_index(record: &JobRecord) -> usize {
+    record.id.strip_prefix(record.parent.as_str()).and_then(|n| n.get(1..)).and_then(|n| n.parse().ok()).unwrap_or(0)
+}
+
+fn is_array(record: &JobRecord) -> bool {
+    record.id.strip_prefix(record.parent.as_str()).is_some_and(|n| n.starts_with('_'))
+}
+
+// Lists the jobs, with the jobs of each pipeline or array collapsed in a
+// single job named after it
+fn group_jobs(records: &[JobRecord]) -> Vec<JobData> {
+    let mut jobs = vec![];
+    let mut parents: Vec<&str> = vec![];
+    for record in records.iter() {
+        if record.parent.is_empty() {jobs.push(JobData::from_record(record)); continue}
+        if parents.contains(&record.parent.as_str()) {continue}
+        parents.push(&record.parent);
+        let mut steps = records.iter().filter(|r| r.parent == record.parent).collect::<Vec<&JobRecord>>();
+        steps.sort_by_key(|r| group_index(r));
+        jobs.push(JobData::from_group(&record.parent, &steps, is_array(record)));
+    }
+    jobs
+}
+
+// Describes the [summary] table recorded by the daemon from the output, e.g.
+// "optimization converged in 12 cycles, 0 imaginary frequencies, E = -1234.567890 Eh"
+fn describe_summary(summary: &toml::Value) -> String {
+    let integer = |key: &str| summary.get(key).and_then(|v| v.as_integer());
+    let mut parts = vec![];
+    if let Some(cycles) = integer("optcycles") {
+        match summary.get("converged").and_then(|v| v.as_bool()) {
+            Some(true) => parts.push(format!("optimization converged in {} cycles", cycles)),
+            _ => parts.push(format!("optimization not converged after {} cycles", cycles)),
         }
     }
-    Err(io::Error::new(io::ErrorKind::NotFound, format!("Cannot check on job status {}", job)))
+    if let Some(imaginary) = integer("imaginary") {
+        parts.push(format!("{} imaginary frequenc{}", imaginary, if imaginary == 1 {"y"} else {"ies"}));
+    }
+    if let Some(energy) = summary.get("energy").and_then(|v| v.as_float()) {
+        parts.push(format!("E = {:.6} Eh", energy));
+    }
+    if let Some(runtime) = integer("runtime") {
+        parts.push(format!("run time {}s", runtime));
+    }
+    if let Some(error) = summary.get("error").and_then(|v| v.as_str()) {
+        parts.push(format!("error: {}", error));
+    }
+    parts.join(", ")
 }
-fn readjobs(path:&path::PathBuf) -> io::Result<Vec<JobData>> {
-    match fs::read_to_string(path) {
-        Err(e) => {return Err(e);},
-        Ok(jobs) => {
-            Ok(jobs.lines()
-                .map(|l| l.trim().to_string())
-                .map(|id| readjob(&id))
-                .filter_map(Result::ok)
-                .collect::<Vec<JobData>>())
+
+// Output of a job in its job folder
+fn job_outpath(job: &str) -> io::Result<path::PathBuf> {
+    let jobdir = jobs_fold().join(job);
+    let (_, jobtoml) = read_jobfile(&jobdir)?;
+    jobtoml.get("launch").and_then(|l| l.get("output")).and_then(|v| v.as_str())
+        .and_then(|p| path::Path::new(p).file_name())
+        .map(|name| jobdir.join(name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Missing launch.output in the jobfile of {}", job)))
+}
+
+// Describes the current phase of a running job from the end of its output,
+// None if the output does not tell yet
+fn job_progress(job: &str) -> Option<String> {
+    let progress = analysis::progress(&job_outpath(job).ok()?).ok()?.describe();
+    if progress.is_empty() {None} else {Some(progress)}
+}
+
+// Time between two reads of the output by orcajob watch
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+// Prints the progress of a job each time it changes, or with output the new
+// content of its output, until the job ends
+fn watch_job(prefix: &str, output: bool) -> io::Result<String> {
+    let job = find_job(prefix)?;
+    let outpath = job_outpath(&job)?;
+    let mut waiting = false;
+    let mut lastprogress = String::new();
+    let mut offset = 0;
+    loop {
+        let record = read_jobs()?.into_iter().find(|r| r.id == job)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Job {} no longer exists", job)))?;
+        match record.state {
+            State::Running | State::Done if output => offset = print_output(&outpath, offset)?,
+            State::Running => {
+                let progress = job_progress(&job).unwrap_or_default();
+                if !progress.is_empty() && progress != lastprogress {
+                    println!("[{}s] {}", timestamp().saturating_sub(record.launched), progress);
+                    lastprogress = progress;
+                }
+            },
+            State::Done => (),
+            _ if waiting => (),
+            _ => {
+                println!("Job {} is {}, waiting for it to start", job, JobData::from_record(&record).status.name());
+                waiting = true;
+            },
         }
+        if record.state == State::Done {
+            let mut message = format!("Job {} ended with status {}", job, record.status);
+            let summary = read_jobfile(&jobs_fold().join(&job)).ok().and_then(|(_, jobtoml)| jobtoml.get("summary").map(describe_summary));
+            if let Some(summary) = summary.filter(|s| !s.is_empty()) {message.push_str(&format!("\nSummary: {}", summary));}
+            return Ok(message)
+        }
+        thread::sleep(WATCH_INTERVAL);
     }
 }
 
-fn is_selected(jd: &JobData, old: &bool, running: &bool, completed: &bool, active: &bool, all: &bool, user: &bool, currentuser: &String) -> bool {
+// Prints the output from offset on and returns the new offset. A restarted
+// job writes a new output, which is then printed from the start.
+fn print_output(outpath: &path::Path, offset: u64) -> io::Result<u64> {
+    let mut outfile = match fs::File::open(outpath) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+        Ok(outfile) => outfile,
+    };
+    let size = outfile.metadata()?.len();
+    let offset = if size < offset {0} else {offset};
+    outfile.seek(io::SeekFrom::Start(offset))?;
+    let mut content = vec![];
+    outfile.read_to_end(&mut content)?;
+    let mut stdout = io::stdout();
+    stdout.write_all(&content)?;
+    stdout.flush()?;
+    Ok(offset + content.len() as u64)
+}
+
+fn is_selected(jd: &JobData, running: bool, completed: bool, active: bool, user: bool, currentuser: &str) -> bool {
     let select_flag = match jd.status {
-        Status::ACTIVE => running.clone(),
-        Status::DONE => completed.clone(),
-        Status::QUEUED => active.clone(),
-        Status::ERROR => completed.clone(),
-        Status::FAILED => completed.clone(),
+        Status::ACTIVE => running,
+        Status::DONE => completed,
+        Status::QUEUED => active,
+        Status::WAITING => active,
+        Status::HELD => active,
+        Status::REQUEUED => active,
+        Status::ERROR => completed,
+        Status::FAILED => completed,
+        Status::TIMEOUT => completed,
+        Status::CANCELLED => completed,
     };
-    let select_uname = if jd.user == currentuser.clone() {true} else {user.clone()};
+    let select_uname = jd.user == currentuser || user;
     // TODO: select based off of oldness
     select_flag && select_uname
 }
 
-fn get_status(old: &bool, running: &bool, completed: &bool, active: &bool, all: &bool, user: &bool, id: Option<&String>) -> io::Result<String> {
-    let mut alljobs: Vec<JobData> = vec![];
-    alljobs.extend(readjobs(&path::PathBuf::from(JOBS_FILE))?);
-    alljobs.extend(readjobs(&path::PathBuf::from(WORK_FILE))?);
-    alljobs.extend(readjobs(&path::PathBuf::from(DONE_FILE))?);
+fn get_status(running: bool, completed: bool, active: bool, user: bool, id: Option<&String>) -> io::Result<String> {
+    let (records, usage) = read_status()?;
+    let alljobs = group_jobs(&records);
 
     match id {
         Some(id) => {
-            match alljobs.iter().filter(|j| j.id.starts_with(id)).next() {
+            // The jobs of pipelines and arrays are found by their own id too
+            let step = records.iter().find(|r| !r.parent.is_empty() && r.id.starts_with(id.as_str())).map(JobData::from_record);
+            match alljobs.iter().find(|j| j.id.starts_with(id)).or(step.as_ref()) {
                 None => {return Err(io::Error::new(io::ErrorKind::InvalidInput, "No job with specified id"));},
+                Some(job) if job.progress.is_some() => {
+                    let mut steps = records.iter().filter(|r| r.parent == job.id).collect::<Vec<&JobRecord>>();
+                    steps.sort_by_key(|r| group_index(r));
+                    let array = steps.first().is_some_and(|r| is_array(r));
+                    println!("{} {}: {}", if array {"Array"} else {"Pipeline"}, job.id, job.status_name());
+                    let mut table = Table::new();
+                    table.add_row(row![if array {"INPUT"} else {"STEP"}, "ID", "STATUS", "TIME"]);
+                    for record in steps {
+                        let jd = JobData::from_record(record);
+                        table.add_row(row![record.step, jd.id, jd.status_name(), jd.elapsed()]);
+                    }
+                    let mut format = prettytable::format::TableFormat::new();
+                    format.padding(0, 3);
+                    table.set_format(format);
+                    table.printstd();
+                },
                 Some(job) => {
-                    // TODO: pretty print a single line
-                    println!("{:?}", job);
+                    println!("Job {}: {}", job.id, job.status_name());
+                    println!("User: {}", job.user);
+                    println!("Time: {}", job.elapsed());
+                    if let Some(progress) = job_progress(&job.id).filter(|_| matches!(job.status, Status::ACTIVE)) {
+                        println!("Progress: {}", progress);
+                    }
+                    let summary = read_jobfile(&jobs_fold().join(&job.id)).ok().and_then(|(_, jobtoml)| jobtoml.get("summary").map(describe_summary));
+                    if let Some(summary) = summary.filter(|s| !s.is_empty()) {println!("Summary: {}", summary);}
+                    let history = records.iter().find(|r| r.id == job.id).map(|r| r.history.as_slice()).unwrap_or_default();
+                    let mut table = Table::new();
+                    table.add_row(row!["TIME", "STATE"]);
+                    for transition in history {
+                        let state = if transition.status.is_empty() {transition.state.name().to_uppercase()} else {transition.status.clone()};
+                        table.add_row(row![transition.time, state]);
+                    }
+                    let mut format = prettytable::format::TableFormat::new();
+                    format.padding(0, 3);
+                    table.set_format(format);
+                    if !history.is_empty() {table.printstd();}
                 }
             }
         }
         None => {
+            println!("Cores: {} used, {} free, {} total", usage.usedcores, usage.maxproc.saturating_sub(usage.usedcores), usage.maxproc);
+            if usage.maxmem > 0 {
+                println!("Memory: {} MB used, {} MB free, {} MB total", usage.usedmemory, usage.maxmem.saturating_sub(usage.usedmemory), usage.maxmem);
+            }
+
             let mut table = Table::new();
-            if *user {table.add_row(row!["ID", "START", "STATUS", "USER", "TIME"]);}
-            else {table.add_row(row!["ID", "START", "STATUS", "TIME"]);}
+            if user {table.add_row(row!["ID", "START", "STATUS", "USER", "TIME", "PROGRESS"]);}
+            else {table.add_row(row!["ID", "START", "STATUS", "TIME", "PROGRESS"]);}
             
             let currentuser = whoami::username();
             
             for jd in alljobs.iter()
-                                    .filter(|j| is_selected(j, old, running, completed, active, all, user, &currentuser))
+                                    .filter(|j| is_selected(j, running, completed, active, user, &currentuser))
             {
-                let timestr = match jd.ended {
-                    0 => (jd.ended - jd.launched).to_string(),
-                    _ => "-".to_string(),
+                let timestr = jd.elapsed();
+                let status = jd.status_name();
+                // The progress of a pipeline or an array is the one of its running job
+                let runningjob = match jd.progress {
+                    None => Some(jd.id.as_str()).filter(|_| matches!(jd.status, Status::ACTIVE)),
+                    Some(_) => records.iter().find(|r| r.parent == jd.id && r.state == State::Running).map(|r| r.id.as_str()),
                 };
-                let status = match jd.status{
-                    Status::FAILED => "FAILED",
-                    Status::QUEUED => "QUEUED",
-                    Status::ACTIVE => "ACTIVE",
-                    Status::DONE => "DONE",
-                    Status::ERROR => "ERROR",
-                }.to_string();
-                if *user {table.add_row(row![jd.id, jd.scheduled, status, jd.user, timestr]);}
-                else {table.add_row(row![jd.id, jd.scheduled, status, timestr]);}
+                let progress = runningjob.and_then(job_progress).unwrap_or_else(|| "-".to_string());
+                if user {table.add_row(row![jd.id, jd.scheduled, status, jd.user, timestr, progress]);}
+                else {table.add_row(row![jd.id, jd.scheduled, status, timestr, progress]);}
             }
             let mut format = prettytable::format::TableFormat::new();
             format.padding(0, 3);
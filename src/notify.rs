@@ -0,0 +1,191 @@
+use crate::common::{conf_file, jobs_fold, read_orcarc, timestamp, user_id};
+use serde::Serialize;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use toml::Value;
+
+// Events that can be listed in notify.events
+pub const EVENTS: [&str; 6] = ["started", "finished", "failed", "timeout", "cancelled", "restarted"];
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Maps the final status of a job to the event it triggers
+pub fn status_event(status: &str) -> &'static str {
+    match status {
+        "DONE" => "finished",
+        "TIMEOUT" => "timeout",
+        "CANCELLED" => "cancelled",
+        _ => "failed",
+    }
+}
+
+// Job metadata passed to every backend, posted as json to the webhook
+#[derive(Serialize)]
+struct Notification {
+    id: String,
+    event: String,
+    name: String,
+    user: String,
+    status: String,
+    reason: String,
+    path: String,
+    dir: String,
+    time: u64,
+}
+
+impl Notification {
+    fn new(job: &str, event: &str, jobtoml: &Value) -> Notification {
+        let get = |table: &str, key: &str| -> String {
+            jobtoml.get(table).and_then(|t| t.get(key)).and_then(|v| v.as_str()).unwrap_or("").to_string()
+        };
+        Notification {
+            id: job.to_string(),
+            event: event.to_string(),
+            name: jobtoml.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            user: get("launch", "username"),
+            status: get("result", "status"),
+            reason: get("result", "reason"),
+            path: get("result", "path"),
+            dir: fs::canonicalize(jobs_fold().join(job)).unwrap_or_default().to_string_lossy().to_string(),
+            time: timestamp(),
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, &str); 8] {
+        [
+            ("id", &self.id),
+            ("event", &self.event),
+            ("name", &self.name),
+            ("user", &self.user),
+            ("status", &self.status),
+            ("reason", &self.reason),
+            ("path", &self.path),
+            ("dir", &self.dir),
+        ]
+    }
+
+    fn summary(&self) -> String {
+        let mut summary = format!("Job {} ({}) {}", self.id, self.name, self.event);
+        if !self.reason.is_empty() {summary.push_str(&format!(": {}", self.reason));}
+        summary
+    }
+}
+
+// Formats a unix timestamp as the UTC asctime date used by mbox From lines
+fn asctime(time: u64) -> String {
+    let mut tm: libc::tm = unsafe {std::mem::zeroed()};
+    let mut buffer = [0 as libc::c_char; 64];
+    let length = unsafe {
+        if libc::gmtime_r(&(time as libc::time_t), &mut tm).is_null() {return String::new()}
+        libc::strftime(buffer.as_mut_ptr(), buffer.len(), c"%a %b %e %H:%M:%S %Y".as_ptr(), &tm)
+    };
+    let bytes = buffer[..length].iter().map(|&c| c as u8).collect::<Vec<u8>>();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Runs the command through sh, with the job metadata in ORCAJOB_* variables
+fn run_command(command: &str, notification: &Notification) -> io::Result<()> {
+    let mut process = Command::new("sh");
+    process.arg("-c").arg(command).stdin(Stdio::null());
+    for (key, value) in notification.fields() {
+        process.env(format!("ORCAJOB_{}", key.to_uppercase()), value);
+    }
+    process.env("ORCAJOB_TIME", notification.time.to_string());
+    let status = process.status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("Notify command exited with {}", status)))
+    }
+    Ok(())
+}
+
+// Appends a message in mbox format to the mailbox file
+fn append_mailbox(mailbox: &str, notification: &Notification) -> io::Result<()> {
+    let mut body = String::new();
+    for (key, value) in notification.fields() {
+        body.push_str(&format!("{}: {}\n", key, value));
+    }
+    let message = format!("From orcajob {}\nFrom: orcajob\nTo: {}\nSubject: {}\n\n{}\n",
+        asctime(notification.time), notification.user, notification.summary(), body);
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(mailbox)?;
+    file.write_all(message.as_bytes())
+}
+
+// Writes a single line to the fifo. The fifo is opened for reading too, so
+// that opening it does not block when no reader is listening.
+fn write_fifo(fifo: &str, notification: &Notification) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(fifo)?;
+    file.write_all(format!("{}\n", notification.summary()).as_bytes())
+}
+
+// Posts the notification as json to a http://host[:port]/path url
+fn post_webhook(url: &str, notification: &Notification) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported webhook url {}, only http:// is supported", url));
+    let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+    let (hostport, path) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+    let host = hostport.rsplit_once(':').map(|(h, _)| h).unwrap_or(hostport);
+    let address = if hostport.contains(':') {hostport.to_string()} else {format!("{}:80", hostport)};
+    let address = address.to_socket_addrs()?.next().ok_or_else(invalid)?;
+
+    let body = serde_json::to_string(notification)?;
+    let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(stream, "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path, host, body.len(), body)?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let code = response.split_whitespace().nth(1).and_then(|c| c.parse::<u16>().ok()).unwrap_or(0);
+    if !(200..300).contains(&code) {
+        return Err(io::Error::other(format!("Webhook answered {}", response.lines().next().unwrap_or(""))))
+    }
+    Ok(())
+}
+
+// The backends run as the daemon user, and the webhook connects from the
+// daemon host, so they are only used when the daemon runs as the owner of the
+// job, or when the value is the default set by the administrator in orcarc
+fn trusted(key: &str, value: &str, owner: &str) -> bool {
+    let default = read_orcarc(&conf_file()).get("defaultjob").and_then(|d| d.get("notify")).and_then(|n| n.get(key)).cloned();
+    if default.as_ref().and_then(|v| v.as_str()) == Some(value) {return true}
+    user_id(owner).is_some_and(|uid| uid == unsafe {libc::getuid()})
+}
+
+// Sends the event to every backend configured in the notify table of the job,
+// if the event is listed in notify.events. Backends run in a separate thread
+// so that a slow command or webhook does not hold up the daemon.
+pub fn notify(job: &str, event: &str, jobtoml: &Value) {
+    let notify = match jobtoml.get("notify") {
+        None => return,
+        Some(notify) => notify.clone(),
+    };
+    let events = notify.get("events").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    if !events.iter().any(|e| e.as_str() == Some(event)) {return}
+
+    let notification = Notification::new(job, event, jobtoml);
+    thread::spawn(move || {
+        let backend = |key: &str| notify.get(key).and_then(|v| v.as_str()).filter(|v| !v.is_empty());
+        let local = |key: &str| backend(key).filter(|value| {
+            let trusted = trusted(key, value, &notification.user);
+            if !trusted {eprintln!("Skipping the notify {} of job {}, it belongs to {} and is not set in orcarc", key, notification.id, notification.user);}
+            trusted
+        });
+        let mut results = vec![];
+        if let Some(command) = local("command") {results.push(("command", run_command(command, &notification)));}
+        if let Some(mailbox) = local("mailbox") {results.push(("mailbox", append_mailbox(mailbox, &notification)));}
+        if let Some(fifo) = local("fifo") {results.push(("fifo", write_fifo(fifo, &notification)));}
+        if let Some(webhook) = local("webhook") {results.push(("webhook", post_webhook(webhook, &notification)));}
+        for (name, result) in results {
+            if let Err(e) = result {
+                eprintln!("Cannot notify {} of job {} with {}: {}", notification.event, notification.id, name, e);
+            }
+        }
+    });
+}
@@ -0,0 +1,80 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Reads a line of /proc/meminfo, in MB
+fn meminfo(key: &str) -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|l| l.split(':').next() == Some(key))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb / 1024)
+}
+
+// Returns the memory available for new processes in MB
+pub fn available_memory() -> Option<u64> {
+    meminfo("MemAvailable")
+}
+
+// Returns the physical memory of the system in MB
+pub fn total_memory() -> Option<u64> {
+    meminfo("MemTotal")
+}
+
+// Returns the parent pid of a process, read from /proc/<pid>/stat
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name is enclosed in parentheses and may contain spaces
+    let fields = stat.rsplit_once(')')?.1.split_whitespace().collect::<Vec<&str>>();
+    fields.get(1)?.parse::<u32>().ok()
+}
+
+// Lists every process spawned, directly or not, by the given pid
+pub fn descendants(pid: u32) -> Vec<u32> {
+    let processes = match fs::read_dir("/proc") {
+        Err(_) => return vec![],
+        Ok(entries) => entries.flatten()
+            .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse::<u32>().ok()))
+            .filter_map(|p| parent_pid(p).map(|pp| (p, pp)))
+            .collect::<Vec<(u32, u32)>>(),
+    };
+    let mut found = vec![];
+    let mut parents = vec![pid];
+    while let Some(parent) = parents.pop() {
+        for (child, _) in processes.iter().filter(|(_, pp)| *pp == parent) {
+            if !found.contains(child) {
+                found.push(*child);
+                parents.push(*child);
+            }
+        }
+    }
+    found
+}
+
+// Checks whether the process exists and is not a zombie
+pub fn is_alive(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Err(_) => false,
+        Ok(stat) => !matches!(stat.rsplit_once(')').and_then(|(_, f)| f.split_whitespace().next()), Some("Z") | None),
+    }
+}
+
+// Returns the working directory of a process
+pub fn process_cwd(pid: u32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+// Sends a signal (libc::SIGTERM, libc::SIGKILL, ...) to the process group led
+// by pid and to every descendant of pid, so that MPI children that left the
+// group are also reached. Fails if the group cannot be signalled.
+pub fn signal_tree(pid: u32, signal: libc::c_int) -> io::Result<()> {
+    // Listed first, the descendants are reparented once the group exits
+    let children = descendants(pid);
+    let result = unsafe {libc::kill(-(pid as libc::pid_t), signal)};
+    let error = io::Error::last_os_error();
+    // Descendants in the group may have exited already, which is fine
+    for child in children {
+        unsafe {libc::kill(child as libc::pid_t, signal);}
+    }
+    if result != 0 {return Err(error)}
+    Ok(())
+}
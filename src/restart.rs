@@ -0,0 +1,89 @@
+use crate::input::{OrcaInput, Step};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Returns the most recently modified file in dir whose name ends with ext,
+// ignoring the names for which exclude returns true
+fn latest_file(dir: &Path, ext: &str, exclude: impl Fn(&str) -> bool) -> Option<PathBuf> {
+    fs::read_dir(dir).ok()?
+        .flatten()
+        .filter(|e| e.file_name().to_str().is_some_and(|n| n.ends_with(ext) && !exclude(n)))
+        .map(|e| {
+            let modified = e.metadata().and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, e.path())
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+// Copies source to the restart file, unless it already is the restart file
+fn copy_restart_file(source: &Path, dir: &Path, restartname: &str) -> io::Result<()> {
+    if source.file_name().is_some_and(|n| n == restartname) {return Ok(())}
+    fs::copy(source, dir.join(restartname))?;
+    Ok(())
+}
+
+// A change of the input: the lines of the range, numbered from 0, are
+// replaced by the new ones
+type Edit = (Range<usize>, Vec<String>);
+
+// Points the input to the given orbital file, replacing the %moinp line if
+// present or adding both MORead and %moinp otherwise
+fn set_moinp(step: &Step, gbwname: &str) -> Edit {
+    let moinp = format!("%moinp \"{}\"", gbwname);
+    match step.block("moinp") {
+        Some(block) => (block.line - 1..block.line, vec![moinp]),
+        None => (0..0, vec!["! MORead".to_string(), moinp]),
+    }
+}
+
+// Points the input to the given geometry file, replacing either the * xyzfile
+// line or the whole inline * xyz block
+fn set_xyzfile(step: &Step, xyzname: &str) -> Option<Edit> {
+    let geometry = step.geometry.as_ref().filter(|g| g.kind == "xyz" || g.kind == "xyzfile")?;
+    let xyzfile = format!("* xyzfile {} {} {}", geometry.charge, geometry.mult, xyzname);
+    Some((geometry.line - 1..geometry.end, vec![xyzfile]))
+}
+
+// Updates the input of a failed job so that it restarts from the latest
+// orbitals (.gbw) and geometry (.xyz) found in the job folder. The files are
+// copied to <basename>_restart.gbw/.xyz, as orca overwrites its own outputs.
+// Returns the names of the files the input now refers to.
+pub fn patch_input(jobdir: &Path, input: &str) -> io::Result<Vec<String>> {
+    let inputpath = jobdir.join(input);
+    let basename = input.strip_suffix(".inp").unwrap_or(input);
+    let content = fs::read_to_string(&inputpath)?;
+    let orcainput = OrcaInput::parse(&content).map_err(|e| io::Error::new(e.kind(), format!("Cannot patch {}, {}", input, e)))?;
+    // The first step is the one that runs again from the start
+    let step = orcainput.steps.first().cloned().unwrap_or_default();
+    let mut edits = vec![];
+    let mut used = vec![];
+
+    if let Some(gbw) = latest_file(jobdir, ".gbw", |_| false) {
+        let gbwname = format!("{}_restart.gbw", basename);
+        copy_restart_file(&gbw, jobdir, &gbwname)?;
+        edits.push(set_moinp(&step, &gbwname));
+        used.push(gbwname);
+    }
+    // The trajectory contains every optimization step, not only the last one
+    if let Some(xyz) = latest_file(jobdir, ".xyz", |n| n.ends_with("_trj.xyz")) {
+        let xyzname = format!("{}_restart.xyz", basename);
+        copy_restart_file(&xyz, jobdir, &xyzname)?;
+        edits.extend(set_xyzfile(&step, &xyzname));
+        used.push(xyzname);
+    }
+
+    if !used.is_empty() {
+        // The last lines go first, so that the line numbers of the others hold
+        let mut lines = content.lines().map(|l| l.to_string()).collect::<Vec<String>>();
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, replacement) in edits {
+            lines.splice(range, replacement);
+        }
+        fs::write(&inputpath, lines.join("\n") + "\n")?;
+    }
+    Ok(used)
+}
@@ -0,0 +1,621 @@
+use crate::common::{acquire_lock_wait, read_ids, read_jobfile, release_lock, set_jobvalue, timestamp, write_atomic, write_jobfile};
+use crate::common::{done_file, done_lock, jobs_file, jobs_fold, jobs_lock, journal_file, journal_lock, work_file, work_lock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use toml::Value;
+
+// The job store is an append-only journal: every state transition appends a
+// full snapshot of the job record on its own line, and the last snapshot of a
+// job wins. Lines are only appended under the journal lock, so a transition either
+// is in the journal or is not, and readers never need the lock.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    #[default]
+    Queued,
+    // Queued once the jobs listed in scheduling.after have ended
+    Waiting,
+    // Queued, but not picked by the scheduler until released
+    Held,
+    Running,
+    Done,
+    // The job folder was deleted by gc, the record goes away on compaction
+    Deleted,
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Queued => "queued",
+            State::Waiting => "waiting",
+            State::Held => "held",
+            State::Running => "running",
+            State::Done => "done",
+            State::Deleted => "deleted",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<State> {
+        match name {
+            "queued" => Some(State::Queued),
+            "waiting" => Some(State::Waiting),
+            "held" => Some(State::Held),
+            "running" => Some(State::Running),
+            "done" => Some(State::Done),
+            "deleted" => Some(State::Deleted),
+            _ => None,
+        }
+    }
+    // Transitions allowed by the store. A job ends as Done whether it ran or
+    // was cancelled, its result.status tells how.
+    pub fn can_become(&self, next: State) -> bool {
+        match self {
+            State::Queued => matches!(next, State::Waiting | State::Held | State::Running | State::Done),
+            State::Waiting => matches!(next, State::Queued | State::Held | State::Done),
+            State::Held => matches!(next, State::Queued | State::Waiting | State::Done),
+            // Back to the queue when the job is restarted
+            State::Running => matches!(next, State::Queued | State::Done),
+            // Back to the queue when the job is requeued
+            State::Done => matches!(next, State::Queued | State::Waiting | State::Deleted),
+            State::Deleted => false,
+        }
+    }
+}
+
+// Statuses a job can end with, recorded with the Done state
+pub const END_STATUSES: [&str; 5] = ["DONE", "ERROR", "FAILED", "TIMEOUT", "CANCELLED"];
+
+// A state transition of a job, with the status the job ended with for Done
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    pub state: State,
+    #[serde(default)]
+    pub status: String,
+    pub time: u64,
+}
+
+impl Transition {
+    fn new(record: &JobRecord) -> Transition {
+        let status = if record.state == State::Done {record.status.clone()} else {String::new()};
+        Transition { state: record.state, status, time: timestamp() }
+    }
+
+    // Written as <state>@<time> or <state>:<status>@<time>
+    fn to_field(&self) -> String {
+        match self.status.is_empty() {
+            true => format!("{}@{}", self.state.name(), self.time),
+            false => format!("{}:{}@{}", self.state.name(), self.status, self.time),
+        }
+    }
+
+    fn from_field(field: &str) -> Option<Transition> {
+        let (state, time) = field.rsplit_once('@')?;
+        let (state, status) = state.split_once(':').unwrap_or((state, ""));
+        Some(Transition { state: State::from_name(state)?, status: status.to_string(), time: time.parse().ok()? })
+    }
+}
+
+// The fields of a job needed to schedule it and to list it, the full job is
+// kept in its job file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub id: String,
+    pub state: State,
+    pub user: String,
+    pub name: String,
+    pub nprocs: usize,
+    // Estimated memory in MB, 0 if unknown
+    #[serde(default)]
+    pub memory: u64,
+    pub priority: i64,
+    // Jobs that must end before this one starts
+    #[serde(default)]
+    pub after: Vec<String>,
+    // Starts the job however the jobs of after ended, instead of only if they
+    // ended with DONE
+    #[serde(default)]
+    pub afterany: bool,
+    // Pipeline or array the job belongs to, with the name of the step or the
+    // input of the element, empty for standalone jobs
+    #[serde(default)]
+    pub parent: String,
+    #[serde(default)]
+    pub step: String,
+    pub scheduled: u64,
+    pub launched: u64,
+    pub ended: u64,
+    // Final status recorded by the daemon, e.g. DONE or TIMEOUT, empty until the job ends
+    pub status: String,
+    // Times the job was put back in the queue by orcajob requeue after it ended
+    #[serde(default)]
+    pub requeues: u64,
+    // Every state the job went through, oldest first
+    #[serde(default)]
+    pub history: Vec<Transition>,
+}
+
+impl JobRecord {
+    // Builds the record of a compiled job file
+    pub fn from_job(id: &str, jobtoml: &Value, state: State) -> JobRecord {
+        let scheduling = jobtoml.get("scheduling");
+        let result = jobtoml.get("result");
+        let integer = |table: Option<&Value>, key: &str| table.and_then(|t| t.get(key)).and_then(|v| v.as_integer());
+        let group = |table: &str, key: &str| jobtoml.get(table).and_then(|p| p.get(key)).and_then(|v| v.as_str()).map(|v| v.to_string());
+        JobRecord {
+            id: id.to_string(),
+            state,
+            user: jobtoml.get("launch").and_then(|l| l.get("username")).and_then(|v| v.as_str()).unwrap_or("notset").to_string(),
+            name: jobtoml.get("name").and_then(|v| v.as_str()).unwrap_or("notset").to_string(),
+            nprocs: integer(scheduling, "nprocs").unwrap_or(1) as usize,
+            memory: integer(scheduling, "memory").unwrap_or(0) as u64,
+            priority: integer(scheduling, "priority").unwrap_or(0),
+            after: scheduling.and_then(|s| s.get("after")).and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(|i| i.as_str().map(|s| s.to_string())).collect())
+                .unwrap_or_default(),
+            afterany: scheduling.and_then(|s| s.get("dependency")).and_then(|v| v.as_str()) == Some("afterany"),
+            parent: group("pipeline", "parent").or_else(|| group("array", "parent")).unwrap_or_default(),
+            step: group("pipeline", "step").or_else(|| group("array", "input")).unwrap_or_default(),
+            scheduled: integer(result, "scheduled").unwrap_or(0) as u64,
+            launched: integer(result, "launched").unwrap_or(0) as u64,
+            ended: integer(result, "ended").unwrap_or(0) as u64,
+            status: result.and_then(|r| r.get("status")).and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            requeues: integer(result, "requeues").unwrap_or(0) as u64,
+            history: vec![],
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let fields = [
+            ("id", self.id.clone()),
+            ("state", self.state.name().to_string()),
+            ("user", self.user.clone()),
+            ("name", self.name.clone()),
+            ("nprocs", self.nprocs.to_string()),
+            ("memory", self.memory.to_string()),
+            ("priority", self.priority.to_string()),
+            ("after", self.after.join(",")),
+            ("afterany", self.afterany.to_string()),
+            ("parent", self.parent.clone()),
+            ("step", self.step.clone()),
+            ("scheduled", self.scheduled.to_string()),
+            ("launched", self.launched.to_string()),
+            ("ended", self.ended.to_string()),
+            ("status", self.status.clone()),
+            ("requeues", self.requeues.to_string()),
+            ("history", self.history.iter().map(|t| t.to_field()).collect::<Vec<String>>().join(",")),
+            ("time", timestamp().to_string()),
+        ];
+        fields.iter().map(|(key, value)| format!("{}={}", key, escape(value))).collect::<Vec<String>>().join("\t")
+    }
+
+    fn from_line(line: &str) -> Option<JobRecord> {
+        let mut record = JobRecord::default();
+        for field in line.split('\t') {
+            let (key, value) = field.split_once('=')?;
+            let value = unescape(value);
+            match key {
+                "id" => record.id = value,
+                "state" => record.state = State::from_name(&value)?,
+                "user" => record.user = value,
+                "name" => record.name = value,
+                "nprocs" => record.nprocs = value.parse().ok()?,
+                "memory" => record.memory = value.parse().ok()?,
+                "priority" => record.priority = value.parse().ok()?,
+                "after" => record.after = value.split(',').filter(|id| !id.is_empty()).map(|id| id.to_string()).collect(),
+                "afterany" => record.afterany = value.parse().ok()?,
+                "parent" => record.parent = value,
+                "step" => record.step = value,
+                "scheduled" => record.scheduled = value.parse().ok()?,
+                "launched" => record.launched = value.parse().ok()?,
+                "ended" => record.ended = value.parse().ok()?,
+                "status" => record.status = value,
+                "requeues" => record.requeues = value.parse().ok()?,
+                "history" => record.history = value.split(',').filter(|t| !t.is_empty()).map(Transition::from_field).collect::<Option<Vec<Transition>>>()?,
+                // Unknown keys are ignored, e.g. the time of the transition
+                _ => (),
+            }
+        }
+        if record.id.is_empty() {return None}
+        Some(record)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {unescaped.push(c); continue}
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Replays the journal, returning the latest record of every job in order of
+// submission and the number of invalid lines, which are reported and skipped.
+// A last line without newline is a write cut short by a crash and is ignored.
+fn read_journal(path: &Path, deleted: bool) -> io::Result<(Vec<JobRecord>, usize)> {
+    let content = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((vec![], 0)),
+        Err(e) => return Err(e),
+        Ok(content) => content,
+    };
+    let complete = match content.rfind('\n') {
+        None => "",
+        Some(end) => &content[..end],
+    };
+
+    let mut records: Vec<JobRecord> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    let mut invalid = 0;
+    for line in complete.lines().filter(|l| !l.trim().is_empty()) {
+        let record = match JobRecord::from_line(line) {
+            None => {
+                eprintln!("Ignoring invalid journal line: {}", line);
+                invalid += 1;
+                continue
+            },
+            Some(record) => record,
+        };
+        match index.get(&record.id) {
+            Some(&i) => records[i] = record,
+            None => {
+                index.insert(record.id.clone(), records.len());
+                records.push(record);
+            },
+        }
+    }
+    if !deleted {records.retain(|r| r.state != State::Deleted);}
+    Ok((records, invalid))
+}
+
+fn replay(path: &Path, deleted: bool) -> io::Result<Vec<JobRecord>> {
+    Ok(read_journal(path, deleted)?.0)
+}
+
+// Reads the current records without taking the lock, for listing jobs
+pub fn read_jobs() -> io::Result<Vec<JobRecord>> {
+    replay(&journal_file(), false)
+}
+
+// Sums the cores requested by the running jobs
+pub fn usedcores(jobs: &[JobRecord]) -> usize {
+    jobs.iter().filter(|j| j.state == State::Running).map(|j| j.nprocs).sum()
+}
+
+// Sums the memory in MB requested by the running jobs
+pub fn usedmemory(jobs: &[JobRecord]) -> u64 {
+    jobs.iter().filter(|j| j.state == State::Running).map(|j| j.memory).sum()
+}
+
+// An open transaction on the journal: the lock is held until the store is dropped
+pub struct Store {
+    lock: File,
+}
+
+impl Store {
+    pub fn open() -> io::Result<Store> {
+        Ok(Store { lock: acquire_lock_wait(&journal_lock())? })
+    }
+
+    pub fn jobs(&self) -> io::Result<Vec<JobRecord>> {
+        replay(&journal_file(), false)
+    }
+
+    // Number of journal lines that cannot be read, whose jobs are missing
+    pub fn invalid_lines(&self) -> io::Result<usize> {
+        Ok(read_journal(&journal_file(), true)?.1)
+    }
+
+    pub fn get(&self, id: &str) -> io::Result<Option<JobRecord>> {
+        Ok(self.jobs()?.into_iter().find(|j| j.id == id))
+    }
+
+    fn append(&self, record: &JobRecord) -> io::Result<()> {
+        let mut file = fs::OpenOptions::new().create(true).read(true).append(true).open(journal_file())?;
+        let mut line = record.to_line() + "\n";
+        // Terminate a line left incomplete by a crash, so that it stays ignored
+        if file.seek(SeekFrom::End(0))? > 0 {
+            let mut last = [0u8; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {line.insert(0, '\n');}
+        }
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+
+    // Adds a new job, failing if the id is already in use. The history of the
+    // job starts with its first state.
+    pub fn insert(&self, record: &JobRecord) -> io::Result<()> {
+        if replay(&journal_file(), true)?.iter().any(|j| j.id == record.id) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Job {} already exists", record.id)))
+        }
+        let mut record = record.clone();
+        if record.history.is_empty() {record.history.push(Transition::new(&record));}
+        self.append(&record)
+    }
+
+    // Applies change to the record of a job, which must be in one of the from
+    // states, and returns the new record. A change of state must be allowed by
+    // State::can_become and is added to the history of the job. Jobs become
+    // Done only with one of END_STATUSES.
+    pub fn update(&self, id: &str, from: &[State], change: impl FnOnce(&mut JobRecord)) -> io::Result<JobRecord> {
+        let mut record = match self.get(id)? {
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, format!("No job {} in the store", id))),
+            Some(record) => record,
+        };
+        if !from.contains(&record.state) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} is {}", id, record.state.name())))
+        }
+        let state = record.state;
+        change(&mut record);
+        if record.state != state {
+            if !state.can_become(record.state) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} cannot go from {} to {}", id, state.name(), record.state.name())))
+            }
+            if record.state == State::Done && !END_STATUSES.contains(&record.status.as_str()) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} cannot end with status {:?}", id, record.status)))
+            }
+            record.history.push(Transition::new(&record));
+        }
+        self.append(&record)?;
+        Ok(record)
+    }
+
+    // Rewrites the journal with a single line per job, dropping the deleted ones
+    pub fn compact(&self) -> io::Result<()> {
+        let content = self.jobs()?.iter().map(|j| j.to_line() + "\n").collect::<String>();
+        write_atomic(&journal_file(), &content)
+    }
+}
+
+impl Drop for Store {
+    fn drop(&mut self) {
+        let _ = release_lock(&self.lock);
+    }
+}
+
+// Adds a job staged by orcajob job in the hidden .<id> folder to the store.
+// The folder is renamed under the lock, so that a crash never leaves a job
+// without its folder or a folder without its job.
+pub fn commit_job(id: &str) -> io::Result<JobRecord> {
+    let staging = jobs_fold().join(format!(".{}", id));
+    let jobfolder = jobs_fold().join(id);
+    let (_, jobtoml) = read_jobfile(&staging)?;
+    let mut record = JobRecord::from_job(id, &jobtoml, State::Queued);
+    if !record.after.is_empty() {record.state = State::Waiting;}
+
+    let store = Store::open()?;
+    fs::rename(&staging, &jobfolder)?;
+    if let Err(e) = store.insert(&record) {
+        fs::remove_dir_all(&jobfolder)?;
+        return Err(e)
+    }
+    Ok(record)
+}
+
+// States of the jobs that did not start yet
+const PENDING: [State; 3] = [State::Queued, State::Waiting, State::Held];
+
+// Ends a job that did not start as CANCELLED, recording key = value in the
+// result table of its job file, e.g. who stopped it
+fn cancel_pending(store: &Store, id: &str, key: &str, value: &str) -> io::Result<()> {
+    let ended = timestamp();
+    let (jobpath, mut jobtoml) = read_jobfile(&jobs_fold().join(id))?;
+    set_jobvalue(&mut jobtoml, "result", "ended", Value::Integer(ended as i64));
+    set_jobvalue(&mut jobtoml, "result", "status", Value::String("CANCELLED".to_string()));
+    set_jobvalue(&mut jobtoml, "result", key, Value::String(value.to_string()));
+    write_jobfile(&jobpath, &jobtoml)?;
+    store.update(id, &PENDING, |r| {
+        r.state = State::Done;
+        r.ended = ended;
+        r.status = "CANCELLED".to_string();
+    })?;
+    Ok(())
+}
+
+// Cancels a job that did not start, recording who stopped it. Returns false if
+// the job is not waiting in the queue.
+pub fn cancel_queued(id: &str, user: &str) -> io::Result<bool> {
+    let store = Store::open()?;
+    if !store.get(id)?.is_some_and(|j| PENDING.contains(&j.state)) {return Ok(false)}
+    cancel_pending(&store, id, "stoppedby", user)?;
+    Ok(true)
+}
+
+// Keeps a queued or waiting job from being started
+pub fn hold_job(id: &str) -> io::Result<()> {
+    Store::open()?.update(id, &[State::Queued, State::Waiting], |r| r.state = State::Held)?;
+    Ok(())
+}
+
+// Puts a held job back in the queue. Jobs with dependencies wait for them
+// again, resolve_dependencies queues them if they already ended.
+pub fn release_job(id: &str) -> io::Result<()> {
+    Store::open()?.update(id, &[State::Held], |r| {
+        r.state = if r.after.is_empty() {State::Queued} else {State::Waiting};
+    })?;
+    Ok(())
+}
+
+// Changes the job file of a job that did not start and updates its record
+// from it. The job file is changed under the lock, so that the daemon never
+// starts the job from a half updated job file.
+pub fn update_pending(id: &str, change: impl FnOnce(&mut Value) -> io::Result<()>) -> io::Result<JobRecord> {
+    let store = Store::open()?;
+    if !store.get(id)?.is_some_and(|j| PENDING.contains(&j.state)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} has already started", id)))
+    }
+    let (jobpath, mut jobtoml) = read_jobfile(&jobs_fold().join(id))?;
+    change(&mut jobtoml)?;
+    write_jobfile(&jobpath, &jobtoml)?;
+
+    let updated = JobRecord::from_job(id, &jobtoml, State::Queued);
+    store.update(id, &PENDING, |r| {
+        // Held jobs stay held, the others wait for their new dependencies
+        if r.state != State::Held {
+            r.state = if updated.after.is_empty() {State::Queued} else {State::Waiting};
+        }
+        r.priority = updated.priority;
+        r.after = updated.after;
+        r.afterany = updated.afterany;
+    })
+}
+
+// Puts a job that ended back in the queue for a new attempt, keeping the
+// output of the previous attempt as <output>.requeue<n>. The result of the
+// previous attempt is cleared and its restarts start again from 0. Jobs with
+// dependencies wait for them again. Returns the number of requeues.
+pub fn requeue_job(id: &str) -> io::Result<u64> {
+    let store = Store::open()?;
+    if !store.get(id)?.is_some_and(|j| j.state == State::Done) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Job {} has not ended", id)))
+    }
+    let jobdir = jobs_fold().join(id);
+    let (jobpath, mut jobtoml) = read_jobfile(&jobdir)?;
+    let requeues = jobtoml.get("result").and_then(|r| r.get("requeues")).and_then(|v| v.as_integer()).unwrap_or(0) + 1;
+
+    let output = jobtoml.get("launch").and_then(|l| l.get("output")).and_then(|v| v.as_str())
+        .and_then(|p| Path::new(p).file_name()).map(|name| name.to_string_lossy().to_string());
+    if let Some(output) = output.filter(|o| jobdir.join(o).exists()) {
+        fs::rename(jobdir.join(&output), jobdir.join(format!("{}.requeue{}", output, requeues)))?;
+    }
+    if let Some(result) = jobtoml.get_mut("result").and_then(|r| r.as_table_mut()) {
+        for key in ["launched", "ended", "pid", "status", "reason", "exitcode", "stoppedby", "copied", "restarts", "laststatus"] {
+            result.remove(key);
+        }
+    }
+    if let Some(jobtable) = jobtoml.as_table_mut() {jobtable.remove("summary");}
+    set_jobvalue(&mut jobtoml, "result", "requeues", Value::Integer(requeues));
+    write_jobfile(&jobpath, &jobtoml)?;
+
+    store.update(id, &[State::Done], |r| {
+        r.state = if r.after.is_empty() {State::Queued} else {State::Waiting};
+        r.launched = 0;
+        r.ended = 0;
+        r.status = String::new();
+        r.requeues = requeues as u64;
+    })?;
+    Ok(requeues as u64)
+}
+
+// Checks the dependencies of a waiting job. Returns None while one of them
+// may still run, otherwise whether the job can start or why it is cancelled.
+fn check_dependencies(job: &JobRecord, jobs: &[JobRecord]) -> Option<Result<(), String>> {
+    let mut pending = false;
+    for dependency in job.after.iter() {
+        match jobs.iter().find(|j| &j.id == dependency) {
+            // Deleted by gc and compacted away, it ended long ago
+            None if job.afterany => (),
+            None => return Some(Err(format!("Dependency {} no longer exists", dependency))),
+            Some(j) if !matches!(j.state, State::Done | State::Deleted) => pending = true,
+            Some(j) if !job.afterany && j.status != "DONE" => {
+                return Some(Err(format!("Dependency {} ended with {}", dependency, j.status)))
+            },
+            Some(_) => (),
+        }
+    }
+    if pending {None} else {Some(Ok(()))}
+}
+
+// Queues the waiting jobs whose dependencies have ended, and cancels those
+// whose afterok dependencies did not end with DONE. Cancelling a job can end
+// the wait of its own dependents, so the jobs are checked until nothing
+// changes. Returns the cancelled jobs with the reason.
+pub fn resolve_dependencies() -> io::Result<Vec<(String, String)>> {
+    let store = Store::open()?;
+    let mut cancelled = vec![];
+    loop {
+        let jobs = replay(&journal_file(), true)?;
+        let mut changed = false;
+        for job in jobs.iter().filter(|j| j.state == State::Waiting) {
+            match check_dependencies(job, &jobs) {
+                None => continue,
+                Some(Ok(())) => {store.update(&job.id, &[State::Waiting], |r| r.state = State::Queued)?;},
+                Some(Err(reason)) => {
+                    cancel_pending(&store, &job.id, "reason", &reason)?;
+                    cancelled.push((job.id.clone(), reason));
+                },
+            }
+            changed = true;
+        }
+        if !changed {return Ok(cancelled)}
+    }
+}
+
+// Status of a job that ended before the daemon recorded result.status,
+// inferred from its output as orcajob status used to do
+fn legacy_status(id: &str, jobtoml: &Value) -> String {
+    let outpath = jobtoml.get("launch").and_then(|l| l.get("output")).and_then(|v| v.as_str())
+        .and_then(|p| Path::new(p).file_name())
+        .map(|name| jobs_fold().join(id).join(name));
+    let ended = outpath.and_then(|p| fs::read(p).ok())
+        .map(|content| String::from_utf8_lossy(&content[content.len().saturating_sub(1024)..]).contains("****ORCA TERMINATED NORMALLY****"));
+    if ended == Some(true) {"DONE".to_string()} else {"ERROR".to_string()}
+}
+
+// Imports the jobs of the jobs.txt, work.txt and done.txt lists used before
+// the journal, then renames the lists to <list>.migrated. Jobs already in the
+// journal are skipped. Returns the number of imported jobs.
+pub fn migrate() -> io::Result<usize> {
+    let store = Store::open()?;
+    let jobslock = acquire_lock_wait(&jobs_lock())?;
+    let worklock = acquire_lock_wait(&work_lock())?;
+    let donelock = acquire_lock_wait(&done_lock())?;
+
+    let result = (|| {
+        let mut known = store.jobs()?.into_iter().map(|j| j.id).collect::<Vec<String>>();
+        let mut imported = 0;
+        // A crash while moving a job between lists can leave it in two of
+        // them, the most advanced one wins. Running jobs that never started
+        // are requeued by the daemon.
+        let lists = [(done_file(), State::Done), (work_file(), State::Running), (jobs_file(), State::Queued)];
+        for (list, state) in lists {
+            for id in read_ids(&list)? {
+                if known.contains(&id) {continue}
+                let jobtoml = match read_jobfile(&jobs_fold().join(&id)) {
+                    Err(e) => {eprintln!("Cannot import job {}: {}", id, e); continue},
+                    Ok((_, jobtoml)) => jobtoml,
+                };
+                let mut record = JobRecord::from_job(&id, &jobtoml, state);
+                if state == State::Done && record.status.is_empty() {
+                    record.status = legacy_status(&id, &jobtoml);
+                }
+                // The history is rebuilt from the times of the job file
+                let transition = |state: State, status: &str, time: u64| Transition { state, status: status.to_string(), time };
+                record.history.push(transition(State::Queued, "", record.scheduled));
+                if record.launched > 0 {record.history.push(transition(State::Running, "", record.launched));}
+                if state == State::Done {record.history.push(transition(State::Done, &record.status, record.ended.max(record.launched)));}
+                store.append(&record)?;
+                known.push(id);
+                imported += 1;
+            }
+        }
+        for list in [jobs_file(), work_file(), done_file()] {
+            if list.exists() {fs::rename(&list, list.with_extension("txt.migrated"))?;}
+        }
+        Ok(imported)
+    })();
+
+    release_lock(&donelock)?;
+    release_lock(&worklock)?;
+    release_lock(&jobslock)?;
+    result
+}
+
+// Returns true if lists from before the journal are still waiting to be migrated
+pub fn needs_migration() -> bool {
+    [jobs_file(), work_file(), done_file()].iter().any(|list| list.exists())
+}